@@ -2,6 +2,8 @@
 mod tests;
 
 pub mod state;
+pub mod observation;
+pub mod vec_env;
 
 pub mod engine {
 	use std::{collections::{HashMap, VecDeque}};
@@ -23,16 +25,24 @@ pub mod engine {
 	pub struct Engine {
 		pub game_history: Vec<SAR>,
 		pub current_state: GameState,
+		pub config: EngineConfig,
 	}
 
-	#[derive(Clone)]
+	#[derive(Debug, Clone)]
 	pub struct EngineConfig {
 		pub board_size: i8,
 		pub fruit_density: f32,
 		pub populate_board: bool,
-		pub random_seed: u64
+		pub random_seed: u64,
+		// how many of Apple, Banana, Orange are placed on the board
+		pub fruit_types: u8,
+		pub win_reward: f32,
+		// added to both players every round the match is still in progress
+		pub step_reward: f32
 	}
 
+	const ALL_FRUIT_TYPES: [FruitType; 3] = [Apple, Banana, Orange];
+	pub const MAX_BOARD_SIZE: i8 = 64;
 
 	impl EngineConfig {
 		pub fn default() -> EngineConfig {
@@ -40,16 +50,48 @@ pub mod engine {
 				board_size: 10,
 				fruit_density: 0.2_f32,
 				populate_board: true,
-				random_seed: 123
+				random_seed: 123,
+				fruit_types: 3,
+				win_reward: 10.0f32,
+				step_reward: -0.1f32
+			}
+		}
+
+		pub fn active_fruit_types(self: &Self) -> &'static [FruitType] {
+			&ALL_FRUIT_TYPES[..(self.fruit_types as usize).min(ALL_FRUIT_TYPES.len())]
+		}
+
+		pub fn validate(self: &Self) -> Result<(), String> {
+			if self.board_size < 2 || self.board_size > MAX_BOARD_SIZE {
+				return Err(format!("board_size must be within [2, {}], got {}", MAX_BOARD_SIZE, self.board_size));
+			}
+			if self.fruit_types < 1 || self.fruit_types as usize > ALL_FRUIT_TYPES.len() {
+				return Err(format!("fruit_types must be within [1, {}], got {}", ALL_FRUIT_TYPES.len(), self.fruit_types));
+			}
+			if self.populate_board && !(self.fruit_density > 0.0 && self.fruit_density <= 1.0) {
+				return Err(format!("fruit_density must be within (0, 1], got {}", self.fruit_density));
 			}
+			if !self.win_reward.is_finite() || !self.step_reward.is_finite() {
+				return Err("rewards must be finite".to_string());
+			}
+
+			// every fruit count may be rounded up to the next odd number, and both players need a free cell
+			let cells = (self.board_size as u32).pow(2);
+			let max_fruit = (self.fruit_density * cells as f32).ceil() as u32 + self.fruit_types as u32;
+			if self.populate_board && max_fruit + 2 > cells {
+				return Err(format!("fruit_density {} leaves no room for the players on a {}x{} board",
+								   self.fruit_density, self.board_size, self.board_size));
+			}
+			Ok(())
 		}
 	}
 
 	impl Engine {
 		pub fn new(conf: EngineConfig) -> Engine {
-			let (board_state, player1, player2) = Engine::initialise_board(conf);
+			let (board_state, player1, player2) = Engine::initialise_board(conf.clone());
 
 			Engine {
+				config: conf,
 				game_history: vec![],
 				current_state: GameState {
 					player1,
@@ -64,6 +106,10 @@ pub mod engine {
 		pub fn apply_move(&mut self, actions: (Action, Action), reward_added: Option<f32>) -> WinState {
 			let state = self.current_state.clone();
 			let actions_copy = actions.clone();
+			let action_masks = (
+				state.get_valid_moves(&state.player1),
+				state.get_valid_moves(&state.player2)
+			);
 
 			self.current_state.player1.reward = 0.0f32;
 			self.current_state.player2.reward = 0.0f32;
@@ -71,36 +117,41 @@ pub mod engine {
 			self.resolve_actions(actions);
 			self.current_state.round += 1;
 
+			let mut step_reward = 0.0f32;
 			let win_state = self.check_gameover();
 			match win_state {
 				WinState::Finished(winner) => {
 					match winner {
 						PlayerWinner::Player1 => {
-							self.current_state.player1.reward += 10.0f32;
+							self.current_state.player1.reward += self.config.win_reward;
 						}
 						PlayerWinner::Player2 => {
-							self.current_state.player1.reward += -10.0f32;
+							self.current_state.player1.reward += -self.config.win_reward;
 						},
 					}
 				}
+				WinState::Draw | WinState::Timeout => {}
 				WinState::InProgress => {
-					let reward = match reward_added {
+					step_reward = match reward_added {
 						Some(amt) => amt,
-						None => -0.1f32
+						None => self.config.step_reward
 					};
-					self.current_state.player1.reward += reward;
 				}
 			}
 
-			let action_mask = self.current_state.get_valid_moves(&self.current_state.player1);
+			// the step penalty is added after the zero-sum split so it applies to both players alike
 			let new_rewards = Engine::make_reward_zerosum(
 				self.current_state.player1.reward, self.current_state.player2.reward);
 			let sar: SAR = SAR {
-				reward: new_rewards.0,
-				actions: actions_copy.0,
+				rewards: (new_rewards.0 + step_reward, new_rewards.1 + step_reward),
+				actions: actions_copy,
 				gamestate: state,
-				action_mask: action_mask,
-				terminal: win_state != WinState::InProgress
+				action_masks,
+				values: (0.0f32, 0.0f32),
+				log_probs: (0.0f32, 0.0f32),
+				logits: (vec![], vec![]),
+				terminal: win_state != WinState::InProgress,
+				truncated: false
 			};
 			self.game_history.push(sar);
 
@@ -108,53 +159,67 @@ pub mod engine {
 			win_state
 		}
 
+		pub fn record_values(&mut self, values: (f32, f32)) {
+			if let Some(sar) = self.game_history.last_mut() {
+				sar.values = values;
+			}
+		}
+
+		pub fn record_log_probs(&mut self, log_probs: (f32, f32)) {
+			if let Some(sar) = self.game_history.last_mut() {
+				sar.log_probs = log_probs;
+			}
+		}
+
+		pub fn record_logits(&mut self, logits: (Vec<f32>, Vec<f32>)) {
+			if let Some(sar) = self.game_history.last_mut() {
+				sar.logits = logits;
+			}
+		}
+
+		// ends a match that hit the round limit, the last step is marked truncated rather than terminal
+		pub fn truncate(&mut self) -> WinState {
+			if self.current_state.match_status == WinState::InProgress {
+				self.current_state.match_status = WinState::Timeout;
+				if let Some(sar) = self.game_history.last_mut() {
+					sar.truncated = true;
+				}
+			}
+			self.current_state.match_status
+		}
+
 		pub fn check_gameover(self: &Self) -> WinState {
 			let most_fruit_counts = self.current_state.board.fruit_counts.values().max();
 			match most_fruit_counts {
 				Some(count) => {
 					if *count == 0 {
-						let player_winner = self.get_winner();
-						WinState::Finished(player_winner)
+						self.get_winner()
 					} else {
 						WinState::InProgress
 					}
 				}
-				None => {
-					let player_winner = self.get_winner();
-					WinState::Finished(player_winner)
-				}
+				None => self.get_winner()
 			}
 		}
 
-		fn get_winner(self: &Self) -> PlayerWinner {
+		fn get_winner(self: &Self) -> WinState {
 			let player1_fruit = &self.current_state.player1.fruit_counts;
 			let player2_fruit = &self.current_state.player2.fruit_counts;
 
-			// count in how many fruit categories did player1 win
-			let mut orange_gt = 0;
-			let mut apple_gt = 0;
-			let mut banana_gt =0;
-
-			if player1_fruit.get(&Apple).unwrap() > player2_fruit.get(&Apple).unwrap() {
-				apple_gt = 1;
-			}
-			if player1_fruit.get(&Banana).unwrap() > player2_fruit.get(&Banana).unwrap() {
-				banana_gt = 1;
-			}
-			if player1_fruit.get(&Orange).unwrap() > player2_fruit.get(&Orange).unwrap() {
-				orange_gt = 1;
-			}
-
-			let player1_wins = apple_gt + banana_gt + orange_gt;
-			// let player1_wins = player1_fruit.values()
-			// 	.zip(player2_fruit.values())
-			// 	.map(|(a, b)| a > b)
-			// 	.filter(|a: &bool| *a)
-			// 	.count();
-
-			match player1_wins {
-				2 | 3 => PlayerWinner::Player1,
-				_ => PlayerWinner::Player2
+			// the player holding more of a fruit takes that category, most categories wins
+			let fruit_types = self.config.active_fruit_types();
+			let category_wins = |own: &HashMap<FruitType, f32>, their: &HashMap<FruitType, f32>| fruit_types.iter()
+				.filter(|fruit| own.get(*fruit).unwrap() > their.get(*fruit).unwrap())
+				.count();
+			let player1_wins = category_wins(player1_fruit, player2_fruit);
+			let player2_wins = category_wins(player2_fruit, player1_fruit);
+
+			if player1_wins > player2_wins {
+				WinState::Finished(PlayerWinner::Player1)
+			} else if player2_wins > player1_wins {
+				WinState::Finished(PlayerWinner::Player2)
+			} else {
+				WinState::Draw
 			}
 		}
 
@@ -249,16 +314,21 @@ pub mod engine {
 			let mut player2 = Player::new(Position::new(1, 1));
 
 			if conf.populate_board {
+				let fruit_types = conf.active_fruit_types();
 				let total_fruit = (conf.fruit_density * ((conf.board_size as u32).pow(2) as f32)).ceil();
-				let dirichlet = Dirichlet::new_with_size(5.0f32, 3).unwrap();
-				let proportions = dirichlet.sample(&mut rng);
+				let proportions = if fruit_types.len() > 1 {
+					let dirichlet = Dirichlet::new_with_size(5.0f32, fruit_types.len()).unwrap();
+					dirichlet.sample(&mut rng)
+				} else {
+					vec![1.0f32]
+				};
 				let fruit_counts = proportions.iter()
 					.map(|a| (a * total_fruit).round() as usize)
 					.map(|a| a + 1 - (a % 2))
 					.collect::<Vec<usize>>();
 
 				let fruit_values = &mut fruit_counts.iter()
-					.zip([Apple, Orange, Banana].iter().copied())
+					.zip(fruit_types.iter().copied())
 					.flat_map(|(count, fruit)| iter::repeat(fruit).take(*count))
 					.collect::<Vec<FruitType>>();
 
@@ -272,7 +342,7 @@ pub mod engine {
 				player1.position = board_positions_queue.pop_front().unwrap();
 				player2.position = board_positions_queue.pop_front().unwrap();
 
-				for (count, fruit_type) in fruit_counts.iter().zip([Apple, Banana, Orange].iter().copied()) {
+				for (count, fruit_type) in fruit_counts.iter().zip(fruit_types.iter().copied()) {
 					let fruit_ref = fruit_counts_map.get_mut(&fruit_type).unwrap();
 					*fruit_ref += count;
 				}
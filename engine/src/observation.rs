@@ -0,0 +1,207 @@
+use crate::engine::MAX_BOARD_SIZE;
+use crate::state::{FruitType, GameState, Player, PlayerIdx, Position};
+
+pub trait ObservationEncoder {
+	// dimensions of one encoded observation on a board of this size, encode returns them flattened in C order
+	fn shape(self: &Self, board_size: i8) -> Vec<usize>;
+	fn encode(self: &Self, current_state: &GameState, idx: PlayerIdx) -> Vec<f32>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EncoderKind {
+	// the (map, own, their, relative) vector of GameState::get_state_vec_view
+	Flat { pad_to: Option<i8> },
+	// channels-first [5, size, size] planes: Apple, Banana, Orange, self, opponent
+	Spatial { pad_to: Option<i8> },
+	// [5, 2 * radius + 1, 2 * radius + 1] crop centred on the player: Apple, Banana, Orange, opponent, off-board
+	Egocentric { radius: i8 },
+	// positions, fruit counts and the offset to the nearest fruit of each type
+	Symbolic,
+}
+
+impl EncoderKind {
+	pub fn build(self: &Self) -> Box<dyn ObservationEncoder + Send> {
+		match *self {
+			EncoderKind::Flat { pad_to } => Box::new(FlatEncoder { pad_to }),
+			EncoderKind::Spatial { pad_to } => Box::new(SpatialEncoder { pad_to }),
+			EncoderKind::Egocentric { radius } => Box::new(EgocentricEncoder { radius }),
+			EncoderKind::Symbolic => Box::new(SymbolicEncoder {}),
+		}
+	}
+
+	pub fn validate(self: &Self, board_size: i8) -> Result<(), String> {
+		match *self {
+			EncoderKind::Flat { pad_to: Some(pad_to) } | EncoderKind::Spatial { pad_to: Some(pad_to) } if pad_to < board_size => {
+				Err(format!("observation padding {} is smaller than board_size {}", pad_to, board_size))
+			}
+			EncoderKind::Egocentric { radius } if radius < 1 || radius > MAX_BOARD_SIZE => {
+				Err(format!("egocentric radius must be within [1, {}], got {}", MAX_BOARD_SIZE, radius))
+			}
+			_ => Ok(())
+		}
+	}
+}
+
+const FRUIT_TYPES: [FruitType; 3] = [FruitType::Apple, FruitType::Banana, FruitType::Orange];
+
+fn players(current_state: &GameState, idx: PlayerIdx) -> (&Player, &Player) {
+	match idx {
+		PlayerIdx::Player1 => (&current_state.player1, &current_state.player2),
+		PlayerIdx::Player2 => (&current_state.player2, &current_state.player1),
+	}
+}
+
+fn fruit_channel(fruit: FruitType) -> usize {
+	match fruit {
+		FruitType::Apple => 0,
+		FruitType::Banana => 1,
+		FruitType::Orange => 2,
+	}
+}
+
+pub struct FlatEncoder {
+	pub pad_to: Option<i8>,
+}
+
+impl ObservationEncoder for FlatEncoder {
+	fn shape(self: &Self, board_size: i8) -> Vec<usize> {
+		vec![GameState::observation_shape(board_size, self.pad_to).len()]
+	}
+
+	fn encode(self: &Self, current_state: &GameState, idx: PlayerIdx) -> Vec<f32> {
+		GameState::get_state_vec_view_padded(current_state, idx, self.pad_to).0
+	}
+}
+
+pub struct SpatialEncoder {
+	pub pad_to: Option<i8>,
+}
+
+impl SpatialEncoder {
+	const CHANNELS: usize = 5;
+
+	fn map_size(self: &Self, board_size: i8) -> usize {
+		self.pad_to.unwrap_or(board_size).max(board_size) as usize
+	}
+}
+
+impl ObservationEncoder for SpatialEncoder {
+	fn shape(self: &Self, board_size: i8) -> Vec<usize> {
+		let map_size = self.map_size(board_size);
+		vec![SpatialEncoder::CHANNELS, map_size, map_size]
+	}
+
+	fn encode(self: &Self, current_state: &GameState, idx: PlayerIdx) -> Vec<f32> {
+		let map_size = self.map_size(current_state.board.size);
+		let plane = map_size * map_size;
+		let mut planes = vec![0.0f32; SpatialEncoder::CHANNELS * plane];
+		// rows are y, columns are x
+		let offset = |channel: usize, pos: (usize, usize)| channel * plane + pos.1 * map_size + pos.0;
+
+		for ((x, y), item) in current_state.board.fruit_map.indexed_iter() {
+			if let Some(fruit) = *item {
+				planes[offset(fruit_channel(fruit), (x, y))] = 1.0f32;
+			}
+		}
+		let (own, their) = players(current_state, idx);
+		planes[offset(3, (own.position.x as usize, own.position.y as usize))] = 1.0f32;
+		planes[offset(4, (their.position.x as usize, their.position.y as usize))] = 1.0f32;
+		planes
+	}
+}
+
+pub struct EgocentricEncoder {
+	pub radius: i8,
+}
+
+impl EgocentricEncoder {
+	const CHANNELS: usize = 5;
+
+	fn crop_size(self: &Self) -> usize {
+		2 * self.radius as usize + 1
+	}
+}
+
+impl ObservationEncoder for EgocentricEncoder {
+	fn shape(self: &Self, _board_size: i8) -> Vec<usize> {
+		vec![EgocentricEncoder::CHANNELS, self.crop_size(), self.crop_size()]
+	}
+
+	fn encode(self: &Self, current_state: &GameState, idx: PlayerIdx) -> Vec<f32> {
+		let crop_size = self.crop_size();
+		let plane = crop_size * crop_size;
+		let mut planes = vec![0.0f32; EgocentricEncoder::CHANNELS * plane];
+		let (own, their) = players(current_state, idx);
+		let size = current_state.board.size as i32;
+
+		for row in 0..crop_size {
+			for col in 0..crop_size {
+				// in i32 since the crop can reach past what an i8 position holds
+				let x = own.position.x as i32 + col as i32 - self.radius as i32;
+				let y = own.position.y as i32 + row as i32 - self.radius as i32;
+				let cell = row * crop_size + col;
+				if x < 0 || x >= size || y < 0 || y >= size {
+					planes[4 * plane + cell] = 1.0f32;
+					continue;
+				}
+				let pos = Position::new(x as i8, y as i8);
+				if let Some(fruit) = current_state.board.fruit_map[[pos.x as usize, pos.y as usize]] {
+					planes[fruit_channel(fruit) * plane + cell] = 1.0f32;
+				}
+				if pos == their.position {
+					planes[3 * plane + cell] = 1.0f32;
+				}
+			}
+		}
+		planes
+	}
+}
+
+pub struct SymbolicEncoder {}
+
+impl SymbolicEncoder {
+	const LEN: usize = 22;
+}
+
+impl ObservationEncoder for SymbolicEncoder {
+	fn shape(self: &Self, _board_size: i8) -> Vec<usize> {
+		vec![SymbolicEncoder::LEN]
+	}
+
+	fn encode(self: &Self, current_state: &GameState, idx: PlayerIdx) -> Vec<f32> {
+		let size = current_state.board.size as f32;
+		let (own, their) = players(current_state, idx);
+		let mut features = Vec::with_capacity(SymbolicEncoder::LEN);
+
+		features.push(own.position.x as f32 / size);
+		features.push(own.position.y as f32 / size);
+		features.push(their.position.x as f32 / size);
+		features.push(their.position.y as f32 / size);
+		for fruit in FRUIT_TYPES.iter() {
+			features.push(*own.get_fruit_count(*fruit));
+		}
+		for fruit in FRUIT_TYPES.iter() {
+			features.push(*their.get_fruit_count(*fruit));
+		}
+		for fruit in FRUIT_TYPES.iter() {
+			features.push(*current_state.board.fruit_counts.get(fruit).unwrap_or(&0) as f32);
+		}
+
+		// (present, dx, dy) to the nearest fruit of each type by manhattan distance
+		for fruit in FRUIT_TYPES.iter() {
+			let nearest = current_state.board.fruit_map.indexed_iter()
+				.filter(|(_, item)| **item == Some(*fruit))
+				.map(|((x, y), _)| (x as i32 - own.position.x as i32, y as i32 - own.position.y as i32))
+				.min_by_key(|(dx, dy)| dx.abs() + dy.abs());
+			match nearest {
+				Some((dx, dy)) => {
+					features.push(1.0f32);
+					features.push(dx as f32 / size);
+					features.push(dy as f32 / size);
+				}
+				None => features.extend_from_slice(&[0.0f32, 0.0f32, 0.0f32]),
+			}
+		}
+		features
+	}
+}
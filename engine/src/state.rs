@@ -22,7 +22,11 @@ pub enum Direction {
 #[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub enum WinState {
 	InProgress,
-	Finished(PlayerWinner)
+	Finished(PlayerWinner),
+	// the board was cleared with the fruit categories split evenly
+	Draw,
+	// cut off at the round limit, the match had not been decided
+	Timeout
 }
 
 #[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
@@ -65,7 +69,8 @@ impl Action {
 			Action::Move(dir) => {
 				dir as i32
 			}
-			_ => { 0 }
+			// after the four directions, as in the logits of a model with ALL_LOGITS
+			Action::DoNothing => { 4 }
 		}
 	}
 }
@@ -149,17 +154,43 @@ pub struct GameState {
 #[derive(Debug, Clone)]
 pub struct SAR {
 	pub gamestate: GameState,
-	pub actions: Action,
-	pub action_mask: HashSet<Action>,
-	pub reward: f32,
-	pub terminal: bool
+	pub actions: (Action, Action),
+	pub action_masks: (HashSet<Action>, HashSet<Action>),
+	pub rewards: (f32, f32),
+	pub values: (f32, f32),
+	// log-probability of each action under the policy that chose it
+	pub log_probs: (f32, f32),
+	// masked policy logits in Up, Down, Left, Right order, empty unless the rollout asked for them
+	pub logits: (Vec<f32>, Vec<f32>),
+	pub terminal: bool,
+	// set on the last step of a match cut off at the round limit, the state after it is not terminal
+	pub truncated: bool
 }
 
 #[derive(Debug, Clone)]
 pub struct MatchReplay {
 	pub sars: Vec<SAR>,
 	pub agent_ids: (i32, i32),
-	pub p1_won: bool
+	pub p1_won: bool,
+	pub outcome: WinState,
+	// value estimates of the state after the last step, only set when the match was truncated
+	pub bootstrap_values: (f32, f32)
+}
+
+impl MatchReplay {
+	pub fn new(sars: Vec<SAR>, agent_ids: (i32, i32), outcome: WinState) -> Self {
+		MatchReplay {
+			sars,
+			agent_ids,
+			p1_won: outcome == WinState::Finished(PlayerWinner::Player1),
+			outcome,
+			bootstrap_values: (0.0f32, 0.0f32)
+		}
+	}
+
+	pub fn truncated(self: &Self) -> bool {
+		self.outcome == WinState::Timeout
+	}
 }
 
 impl BoardState {
@@ -175,6 +206,9 @@ impl BoardState {
 	pub fn update_fruit_counts(&mut self) {
 		let board = &self.fruit_map;
 		let mut fruit_counts = HashMap::<FruitType, usize>::new();
+		for fruit_type in [FruitType::Apple, FruitType::Banana, FruitType::Orange].iter() {
+			fruit_counts.insert(*fruit_type, 0);
+		}
 
 		for fruit_cell in board.iter() {
 			match *fruit_cell {
@@ -279,7 +313,22 @@ impl GameState {
 		return (pos.x < 0 || pos.x >= board_size) || (pos.y < 0 || pos.y >= board_size);
 	}
 
+	// shape of the flat observation for a board, pad_to grows the fruit map so one model fits several sizes
+	pub fn observation_shape(board_size: i8, pad_to: Option<i8>) -> ObservationShape {
+		let map_size = pad_to.unwrap_or(board_size).max(board_size) as usize;
+		ObservationShape {
+			map_size,
+			map_channels: 3,
+			features: 14,
+		}
+	}
+
 	pub fn get_state_vec_view(current_state: &GameState, idx: PlayerIdx) -> (Vec<f32>, Vec<f32>) {
+		GameState::get_state_vec_view_padded(current_state, idx, None)
+	}
+
+	// 1.0 marks an action the player can't take, in the order Up, Down, Left, Right
+	pub fn get_action_mask_vec(current_state: &GameState, idx: PlayerIdx) -> Vec<f32> {
 		let action_mask = match idx {
 			PlayerIdx::Player1 => {
 				current_state.get_valid_moves(&current_state.player1)
@@ -310,9 +359,15 @@ impl GameState {
 				_ => {}
 			}
 		}
-		let action_mask_vec = Array::from_iter(action_mask_array.iter().cloned()).to_vec();
+		Array::from_iter(action_mask_array.iter().cloned()).to_vec()
+	}
+
+	pub fn get_state_vec_view_padded(current_state: &GameState, idx: PlayerIdx, pad_to: Option<i8>) -> (Vec<f32>, Vec<f32>) {
+		let action_mask_vec = GameState::get_action_mask_vec(current_state, idx);
 
-		let mut map = Array::zeros((10, 10, 3));
+		let shape = GameState::observation_shape(current_state.board.size, pad_to);
+		let map_size = shape.map_size as f32;
+		let mut map = Array::zeros((shape.map_size, shape.map_size, shape.map_channels));
 
 		let fruit_map = &current_state.board.fruit_map;
 		for (idx, item) in fruit_map.indexed_iter() {
@@ -355,14 +410,14 @@ impl GameState {
 				own_info_vec.push(*player1.fruit_counts.get(&FruitType::Apple).unwrap());
 				own_info_vec.push(*player1.fruit_counts.get(&FruitType::Banana).unwrap());
 				own_info_vec.push(*player1.fruit_counts.get(&FruitType::Orange).unwrap());
-				own_info_vec.push(player1.position.x as f32 / 10.0f32);
-				own_info_vec.push(player1.position.y as f32 / 10.0f32);
+				own_info_vec.push(player1.position.x as f32 / map_size);
+				own_info_vec.push(player1.position.y as f32 / map_size);
 
 				their_info_vec.push(*player2.fruit_counts.get(&FruitType::Apple).unwrap());
 				their_info_vec.push(*player2.fruit_counts.get(&FruitType::Banana).unwrap());
 				their_info_vec.push(*player2.fruit_counts.get(&FruitType::Orange).unwrap());
-				their_info_vec.push(player2.position.x as f32 / 10.0f32);
-				their_info_vec.push(player2.position.y as f32 / 10.0f32);
+				their_info_vec.push(player2.position.x as f32 / map_size);
+				their_info_vec.push(player2.position.y as f32 / map_size);
 
 				let euclidean_distance: f32 = euclidean_dist(player1.position.x as f32, player2.position.x as f32, player1.position.y as f32, player2.position.y as f32);
 				let manhattan_distance: f32 = manhattan_dist(player1.position.x as f32, player2.position.x as f32, player1.position.y as f32, player2.position.y as f32);
@@ -376,14 +431,14 @@ impl GameState {
 				own_info_vec.push(*player2.fruit_counts.get(&FruitType::Apple).unwrap());
 				own_info_vec.push(*player2.fruit_counts.get(&FruitType::Banana).unwrap());
 				own_info_vec.push(*player2.fruit_counts.get(&FruitType::Orange).unwrap());
-				own_info_vec.push(player2.position.x as f32 / 10.0f32);
-				own_info_vec.push(player2.position.y as f32 / 10.0f32);
+				own_info_vec.push(player2.position.x as f32 / map_size);
+				own_info_vec.push(player2.position.y as f32 / map_size);
 
 				their_info_vec.push(*player1.fruit_counts.get(&FruitType::Apple).unwrap());
 				their_info_vec.push(*player1.fruit_counts.get(&FruitType::Banana).unwrap());
 				their_info_vec.push(*player1.fruit_counts.get(&FruitType::Orange).unwrap());
-				their_info_vec.push(player1.position.x as f32 / 10.0f32);
-				their_info_vec.push(player1.position.y as f32 / 10.0f32);
+				their_info_vec.push(player1.position.x as f32 / map_size);
+				their_info_vec.push(player1.position.y as f32 / map_size);
 
 				let euclidean_distance: f32 = euclidean_dist(player2.position.x as f32, player1.position.x as f32, player2.position.y as f32, player1.position.y as f32);
 				let manhattan_distance: f32 = manhattan_dist(player2.position.x as f32, player1.position.x as f32, player2.position.y as f32, player1.position.y as f32);
@@ -403,6 +458,21 @@ impl GameState {
 	}
 }
 
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
+pub struct ObservationShape {
+	// the fruit map is map_size x map_size x map_channels, flattened before the player features
+	pub map_size: usize,
+	pub map_channels: usize,
+	pub features: usize,
+}
+
+impl ObservationShape {
+	pub fn len(self: &Self) -> usize {
+		self.map_size * self.map_size * self.map_channels + self.features
+	}
+}
+
+#[derive(Eq, Hash, PartialEq, Debug, Clone, Copy)]
 pub enum PlayerIdx {
 	Player1,
 	Player2,
@@ -5,13 +5,16 @@ mod tests {
 	use std::collections::HashMap;
 	use crate::engine::FruitType::*;
 	use crate::engine::Direction::*;
+	use crate::observation::*;
+	use crate::vec_env::*;
 
 	fn blank_engine() -> Engine {
 		Engine::new(EngineConfig {
 			board_size: 5,
 			populate_board: false,
 			fruit_density: 0.0,
-			random_seed: 123
+			random_seed: 123,
+			..EngineConfig::default()
 		})
 	}
 
@@ -30,7 +33,7 @@ mod tests {
 		engine.current_state.player1 = Player::new(Position::new(4, 3));
 		engine.current_state.player2 = Player::new(Position::new(2, 3));
 		engine.apply_move((Action::Move(Direction::Down),
-						   Action::Move(Direction::Right)));
+						   Action::Move(Direction::Right)), None);
 
 		assert_eq!(engine.current_state.player1.position, Position::new(4, 4));
 		assert_eq!(engine.current_state.player2.position, Position::new(3, 3));
@@ -42,12 +45,12 @@ mod tests {
 		engine.current_state.player1 = Player::new(Position::new(0, 0));
 		engine.current_state.player2 = Player::new(Position::new(4, 4));
 
-		let p1_valid_moves = engine.get_valid_moves(&engine.current_state.player1);
+		let p1_valid_moves = engine.current_state.get_valid_moves(&engine.current_state.player1);
 		assert!(!p1_valid_moves.contains(&Action::Move(Direction::Up)));
 		assert!(p1_valid_moves.contains(&Action::Move(Direction::Down)));
 
 		engine.apply_move((Action::Move(Direction::Up),
-						   Action::Move(Direction::Right)));
+						   Action::Move(Direction::Right)), None);
 
 		assert_eq!(engine.current_state.player1.position, Position::new(0, 0));
 		assert_eq!(engine.current_state.player2.position, Position::new(4, 4));
@@ -59,7 +62,7 @@ mod tests {
 		engine.current_state.player1 = Player::new(Position::new(4, 3));
 		engine.current_state.player2 = Player::new(Position::new(2, 3));
 		engine.apply_move((Action::Move(Direction::Left),
-						   Action::Move(Direction::Right)));
+						   Action::Move(Direction::Right)), None);
 
 		assert_eq!(engine.current_state.player1.position, Position::new(4, 3));
 		assert_eq!(engine.current_state.player2.position, Position::new(2, 3));
@@ -76,7 +79,7 @@ mod tests {
 		assert_eq!(*engine.current_state.player1.get_fruit_count(Orange), 0.0f32);
 
 		engine.apply_move((Action::Move(Direction::Left),
-						   Action::DoNothing));
+						   Action::DoNothing), None);
 		assert_eq!(engine.current_state.board.get_fruit(3, 3), None);
 		assert_eq!(*engine.current_state.player1.get_fruit_count(Orange), 1.0f32);
 		assert_eq!(*engine.current_state.board.fruit_counts.get(&Orange).unwrap(), 0);
@@ -93,7 +96,7 @@ mod tests {
 		assert_eq!(*engine.current_state.player1.get_fruit_count(Orange), 0.0f32);
 
 		engine.apply_move((Action::Move(Direction::Left),
-						   Action::Move(Direction::Right)));
+						   Action::Move(Direction::Right)), None);
 		assert_eq!(engine.current_state.board.get_fruit(3, 3), None);
 		assert_eq!(*engine.current_state.player1.get_fruit_count(Orange), 0.5f32);
 		assert_eq!(*engine.current_state.player2.get_fruit_count(Orange), 0.5f32);
@@ -113,13 +116,13 @@ mod tests {
 
 		// engine.current_state.print_state();
 
-		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing));
+		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing), None);
 		assert_eq!(win_state, WinState::InProgress);
 
-		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing));
+		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing), None);
 		assert_eq!(win_state, WinState::InProgress);
 
-		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing));
+		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing), None);
 		assert_eq!(win_state, WinState::Finished(PlayerWinner::Player1));
 	}
 
@@ -135,13 +138,13 @@ mod tests {
 
 		// engine.current_state.print_state();
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)));
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)), None);
 		assert_eq!(win_state, WinState::InProgress);
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)));
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)), None);
 		assert_eq!(win_state, WinState::InProgress);
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)));
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Direction::Down)), None);
 		assert_eq!(win_state, WinState::Finished(PlayerWinner::Player2));
 	}
 
@@ -154,7 +157,7 @@ mod tests {
 		engine.current_state.player1 = Player::new(Position::new(4, 4));
 		engine.current_state.player2 = Player::new(Position::new(2, 1));
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::DoNothing));
+		let win_state = engine.apply_move((Action::DoNothing, Action::DoNothing), None);
 		assert_eq!(win_state, WinState::InProgress);
 
 		*engine.current_state.player1.fruit_counts.get_mut(&Apple).unwrap() = 7.0f32;
@@ -165,7 +168,7 @@ mod tests {
 		*engine.current_state.player2.fruit_counts.get_mut(&Banana).unwrap() = 3.0f32;
 		*engine.current_state.player2.fruit_counts.get_mut(&Orange).unwrap() = 20.0f32;
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Down)));
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Down)), None);
 		assert_eq!(win_state, WinState::Finished(PlayerWinner::Player1));
 	}
 
@@ -178,7 +181,7 @@ mod tests {
 		engine.current_state.player1 = Player::new(Position::new(4, 4));
 		engine.current_state.player2 = Player::new(Position::new(2, 1));
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::DoNothing));
+		let win_state = engine.apply_move((Action::DoNothing, Action::DoNothing), None);
 		assert_eq!(win_state, WinState::InProgress);
 
 		*engine.current_state.player1.fruit_counts.get_mut(&Apple).unwrap() = 5.0f32;
@@ -189,7 +192,300 @@ mod tests {
 		*engine.current_state.player2.fruit_counts.get_mut(&Banana).unwrap() = 6.0f32;
 		*engine.current_state.player2.fruit_counts.get_mut(&Orange).unwrap() = 10.0f32;
 
-		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Down)));
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Down)), None);
 		assert_eq!(win_state, WinState::Finished(PlayerWinner::Player2));
 	}
-}
\ No newline at end of file
+
+	#[test]
+	fn fruit_counts_draw() {
+		let mut engine = blank_engine();
+		engine.current_state.board.update_fruit_counts();
+		engine.current_state.board.set_fruit(2,2, Some(Apple));
+
+		engine.current_state.player1 = Player::new(Position::new(4, 4));
+		engine.current_state.player2 = Player::new(Position::new(2, 1));
+
+		// one category each and the apples split evenly
+		*engine.current_state.player1.fruit_counts.get_mut(&Banana).unwrap() = 5.0f32;
+		*engine.current_state.player2.fruit_counts.get_mut(&Orange).unwrap() = 5.0f32;
+		*engine.current_state.player1.fruit_counts.get_mut(&Apple).unwrap() = 1.0f32;
+
+		let win_state = engine.apply_move((Action::DoNothing, Action::Move(Down)), None);
+		assert_eq!(win_state, WinState::Draw);
+
+		let sar = engine.game_history.last().unwrap();
+		assert!(sar.terminal);
+		assert_eq!(sar.rewards, (-1.0f32, 1.0f32));
+	}
+
+	#[test]
+	fn truncate_marks_last_step() {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(2, 2, Some(Apple));
+		engine.current_state.player1 = Player::new(Position::new(4, 4));
+		engine.current_state.player2 = Player::new(Position::new(0, 0));
+		engine.apply_move((Action::DoNothing, Action::DoNothing), None);
+		assert_eq!(engine.truncate(), WinState::Timeout);
+		assert_eq!(engine.current_state.match_status, WinState::Timeout);
+
+		let sar = engine.game_history.last().unwrap();
+		assert!(sar.truncated);
+		assert!(!sar.terminal);
+
+		let replay = MatchReplay::new(engine.game_history.clone(), (1, 2), engine.current_state.match_status);
+		assert!(replay.truncated());
+		assert!(!replay.p1_won);
+
+		// a match that already ended keeps its outcome
+		let mut engine = blank_engine();
+		engine.current_state.match_status = WinState::Draw;
+		assert_eq!(engine.truncate(), WinState::Draw);
+	}
+
+	#[test]
+	fn history_records_both_players() {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(3, 3, Some(Orange));
+		engine.current_state.board.set_fruit(0, 0, Some(Apple));
+		engine.current_state.player1 = Player::new(Position::new(4, 3));
+		engine.current_state.player2 = Player::new(Position::new(0, 1));
+
+		engine.apply_move((Action::Move(Direction::Left), Action::Move(Direction::Right)), None);
+		engine.record_values((0.5f32, -0.5f32));
+
+		let sar = engine.game_history.last().unwrap();
+		assert_eq!(sar.actions, (Action::Move(Direction::Left), Action::Move(Direction::Right)));
+		assert_eq!(sar.values, (0.5f32, -0.5f32));
+		assert!(!sar.action_masks.0.contains(&Action::Move(Direction::Right)));
+		assert!(!sar.action_masks.1.contains(&Action::Move(Direction::Left)));
+		assert!(sar.action_masks.1.contains(&Action::Move(Direction::Up)));
+
+		// player 1 picked up an orange, both pay the step penalty
+		assert_eq!(sar.rewards, (1.0f32 - 0.1f32, -1.0f32 - 0.1f32));
+	}
+
+	#[test]
+	fn history_terminal_rewards_zerosum() {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(2, 2, Some(Apple));
+		engine.current_state.player1 = Player::new(Position::new(2, 1));
+		engine.current_state.player2 = Player::new(Position::new(4, 4));
+		*engine.current_state.player1.fruit_counts.get_mut(&Banana).unwrap() = 1.0f32;
+		*engine.current_state.player1.fruit_counts.get_mut(&Orange).unwrap() = 1.0f32;
+
+		let win_state = engine.apply_move((Action::Move(Direction::Down), Action::DoNothing), None);
+		assert_eq!(win_state, WinState::Finished(PlayerWinner::Player1));
+
+		let sar = engine.game_history.last().unwrap();
+		assert!(sar.terminal);
+		assert_eq!(sar.rewards, (11.0f32, -11.0f32));
+	}
+
+	#[test]
+	fn config_single_fruit_type() {
+		let conf = EngineConfig {
+			board_size: 6,
+			fruit_types: 1,
+			fruit_density: 0.3,
+			..EngineConfig::default()
+		};
+		assert!(conf.validate().is_ok());
+
+		let engine = Engine::new(conf);
+		let fruit_counts = &engine.current_state.board.fruit_counts;
+		assert!(*fruit_counts.get(&Apple).unwrap() > 0);
+		assert_eq!(*fruit_counts.get(&Banana).unwrap(), 0);
+		assert_eq!(*fruit_counts.get(&Orange).unwrap(), 0);
+	}
+
+	#[test]
+	fn config_validation() {
+		assert!(EngineConfig::default().validate().is_ok());
+		assert!(EngineConfig { board_size: 1, ..EngineConfig::default() }.validate().is_err());
+		assert!(EngineConfig { fruit_types: 4, ..EngineConfig::default() }.validate().is_err());
+		assert!(EngineConfig { fruit_density: 0.0, ..EngineConfig::default() }.validate().is_err());
+		assert!(EngineConfig { board_size: 3, fruit_density: 0.9, ..EngineConfig::default() }.validate().is_err());
+	}
+
+	#[test]
+	fn config_rewards() {
+		let mut engine = Engine::new(EngineConfig {
+			win_reward: 1.0,
+			step_reward: -0.5,
+			..blank_engine().config
+		});
+		engine.current_state.board.set_fruit(2, 2, Some(Apple));
+		engine.current_state.board.set_fruit(4, 0, Some(Banana));
+		engine.current_state.player1 = Player::new(Position::new(2, 1));
+		engine.current_state.player2 = Player::new(Position::new(4, 2));
+		*engine.current_state.player1.fruit_counts.get_mut(&Orange).unwrap() = 1.0f32;
+
+		engine.apply_move((Action::DoNothing, Action::DoNothing), None);
+		assert_eq!(engine.game_history.last().unwrap().rewards, (-0.5f32, -0.5f32));
+
+		engine.apply_move((Action::Move(Direction::Down), Action::Move(Direction::Up)), None);
+		assert_eq!(engine.current_state.match_status, WinState::InProgress);
+		engine.apply_move((Action::DoNothing, Action::Move(Direction::Up)), None);
+		assert_eq!(engine.current_state.match_status, WinState::Finished(PlayerWinner::Player1));
+		assert_eq!(engine.game_history.last().unwrap().rewards, (0.0f32, 0.0f32));
+	}
+
+	#[test]
+	fn observation_follows_board_size() {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(4, 4, Some(Banana));
+		engine.current_state.player1 = Player::new(Position::new(1, 2));
+
+		let shape = GameState::observation_shape(5, None);
+		let (state, mask) = GameState::get_state_vec_view(&engine.current_state, PlayerIdx::Player1);
+		assert_eq!(state.len(), shape.len());
+		assert_eq!(state.len(), 5 * 5 * 3 + 14);
+		assert_eq!(mask.len(), 4);
+		assert_eq!(state[(4 * 5 + 4) * 3 + 1], 1.0f32);
+		assert_eq!(state[5 * 5 * 3 + 3], 1.0f32 / 5.0f32);
+	}
+
+	#[test]
+	fn observation_padding() {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(4, 4, Some(Banana));
+		engine.current_state.player1 = Player::new(Position::new(1, 2));
+
+		let shape = GameState::observation_shape(5, Some(8));
+		let (state, _) = GameState::get_state_vec_view_padded(&engine.current_state, PlayerIdx::Player1, Some(8));
+		assert_eq!(state.len(), shape.len());
+		assert_eq!(state.len(), 8 * 8 * 3 + 14);
+		assert_eq!(state[(4 * 8 + 4) * 3 + 1], 1.0f32);
+		assert_eq!(state[8 * 8 * 3 + 3], 1.0f32 / 8.0f32);
+	}
+
+	fn encoder_engine() -> Engine {
+		let mut engine = blank_engine();
+		engine.current_state.board.set_fruit(3, 1, Some(Banana));
+		engine.current_state.board.set_fruit(0, 4, Some(Apple));
+		engine.current_state.player1 = Player::new(Position::new(1, 2));
+		engine.current_state.player2 = Player::new(Position::new(2, 1));
+		engine
+	}
+
+	#[test]
+	fn encoders_match_their_shape() {
+		let engine = encoder_engine();
+		let kinds = [
+			EncoderKind::Flat { pad_to: None },
+			EncoderKind::Flat { pad_to: Some(7) },
+			EncoderKind::Spatial { pad_to: None },
+			EncoderKind::Spatial { pad_to: Some(7) },
+			EncoderKind::Egocentric { radius: 2 },
+			EncoderKind::Symbolic,
+		];
+		for kind in kinds.iter() {
+			let encoder = kind.build();
+			let len: usize = encoder.shape(5).iter().product();
+			assert_eq!(encoder.encode(&engine.current_state, PlayerIdx::Player1).len(), len, "{:?}", kind);
+			assert_eq!(encoder.encode(&engine.current_state, PlayerIdx::Player2).len(), len, "{:?}", kind);
+		}
+		assert!(EncoderKind::Spatial { pad_to: Some(4) }.validate(5).is_err());
+		assert!(EncoderKind::Egocentric { radius: 0 }.validate(5).is_err());
+		assert!(EncoderKind::Egocentric { radius: 65 }.validate(5).is_err());
+		// the widest crop still sees only the 5x5 board, everything else is off-board
+		let widest = EncoderKind::Egocentric { radius: 64 };
+		assert!(widest.validate(5).is_ok());
+		let planes = widest.build().encode(&engine.current_state, PlayerIdx::Player1);
+		let plane = 129 * 129;
+		assert_eq!(planes[4 * plane..].iter().filter(|cell| **cell == 1.0f32).count(), plane - 25);
+	}
+
+	#[test]
+	fn spatial_encoder_planes() {
+		let engine = encoder_engine();
+		let encoder = EncoderKind::Spatial { pad_to: None }.build();
+		let planes = encoder.encode(&engine.current_state, PlayerIdx::Player2);
+		let cell = |channel: usize, x: usize, y: usize| planes[channel * 25 + y * 5 + x];
+
+		assert_eq!(encoder.shape(5), vec![5, 5, 5]);
+		assert_eq!(cell(1, 3, 1), 1.0f32);
+		assert_eq!(cell(0, 0, 4), 1.0f32);
+		assert_eq!(cell(3, 2, 1), 1.0f32);
+		assert_eq!(cell(4, 1, 2), 1.0f32);
+		assert_eq!(planes.iter().sum::<f32>(), 4.0f32);
+	}
+
+	#[test]
+	fn egocentric_encoder_crop() {
+		let engine = encoder_engine();
+		let encoder = EncoderKind::Egocentric { radius: 1 }.build();
+		let planes = encoder.encode(&engine.current_state, PlayerIdx::Player2);
+		let cell = |channel: usize, col: usize, row: usize| planes[channel * 9 + row * 3 + col];
+
+		// player 2 sits at (2, 1): the banana is to the right, player 1 below-left, row above is on the board
+		assert_eq!(cell(1, 2, 1), 1.0f32);
+		assert_eq!(cell(3, 0, 2), 1.0f32);
+		assert_eq!((0..9).map(|cell_idx| planes[4 * 9 + cell_idx]).sum::<f32>(), 0.0f32);
+
+		// player 1 at (1, 2) sees player 2 above-right
+		let planes = encoder.encode(&engine.current_state, PlayerIdx::Player1);
+		assert_eq!(planes[3 * 9 + 2], 1.0f32);
+	}
+
+	#[test]
+	fn symbolic_encoder_nearest_fruit() {
+		let engine = encoder_engine();
+		let features = SymbolicEncoder {}.encode(&engine.current_state, PlayerIdx::Player1);
+
+		assert_eq!(&features[0..4], &[0.2f32, 0.4f32, 0.4f32, 0.2f32]);
+		// apple at (0, 4), banana at (3, 1), no orange
+		assert_eq!(&features[13..16], &[1.0f32, -0.2f32, 0.4f32]);
+		assert_eq!(&features[16..19], &[1.0f32, 0.4f32, -0.2f32]);
+		assert_eq!(&features[19..22], &[0.0f32, 0.0f32, 0.0f32]);
+	}
+
+	fn vec_env(max_rounds: u32) -> VecEnv {
+		let conf = EngineConfig {
+			board_size: 5,
+			fruit_density: 0.3,
+			..EngineConfig::default()
+		};
+		VecEnv::new(conf, 3, max_rounds, EncoderKind::Flat { pad_to: None })
+	}
+
+	#[test]
+	fn vec_env_reset() {
+		let mut env = vec_env(50);
+		let observations = env.reset(&[1, 2, 3]);
+		let len: usize = env.observation_shape().iter().product();
+
+		assert_eq!(observations.len(), 3);
+		for (player1, player2) in observations.iter() {
+			assert_eq!(player1.state.len(), len);
+			assert_eq!(player2.state.len(), len);
+			assert_eq!(player1.action_mask.len(), 4);
+		}
+
+		// the same seed gives the same board
+		let again = env.reset(&[1, 5, 6]);
+		assert_eq!(again[0], observations[0]);
+	}
+
+	#[test]
+	fn vec_env_truncates_and_resets() {
+		let mut env = vec_env(2);
+		let initial = env.reset(&[1, 2, 3]);
+		let actions = vec![(Action::DoNothing, Action::DoNothing); 3];
+
+		let result = env.step(&actions);
+		assert_eq!(result.truncated, vec![false; 3]);
+		assert_eq!(result.terminated, vec![false; 3]);
+		assert_eq!(result.rewards[0], (-0.1f32, -0.1f32));
+
+		let result = env.step(&actions);
+		assert_eq!(result.truncated, vec![true; 3]);
+		for (env_idx, info) in result.infos.iter().enumerate() {
+			assert_eq!(info.round, 2);
+			assert_eq!(info.win_state, WinState::Timeout);
+			assert_eq!(info.final_observation.as_ref().unwrap(), &initial[env_idx]);
+		}
+		assert!(env.engines().iter().all(|engine| engine.current_state.round == 0));
+		assert_ne!(result.observations[0], initial[0]);
+	}
+}
@@ -0,0 +1,140 @@
+use crate::engine::{Engine, EngineConfig};
+use crate::observation::{EncoderKind, ObservationEncoder};
+use crate::state::{Action, GameState, PlayerIdx, WinState};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlayerObservation {
+	pub state: Vec<f32>,
+	pub action_mask: Vec<f32>,
+}
+
+// (player 1, player 2) view of one board
+pub type EnvObservation = (PlayerObservation, PlayerObservation);
+
+#[derive(Debug, Clone)]
+pub struct StepInfo {
+	pub win_state: WinState,
+	pub round: u32,
+	// observation the episode ended on, set when the board was reset automatically
+	pub final_observation: Option<EnvObservation>,
+}
+
+#[derive(Debug, Clone)]
+pub struct StepResult {
+	pub observations: Vec<EnvObservation>,
+	pub rewards: Vec<(f32, f32)>,
+	pub terminated: Vec<bool>,
+	pub truncated: Vec<bool>,
+	pub infos: Vec<StepInfo>,
+}
+
+// N boards stepped together, each one starts a new episode as soon as the previous one ends
+pub struct VecEnv {
+	config: EngineConfig,
+	max_rounds: u32,
+	encoder: Box<dyn ObservationEncoder + Send>,
+	engines: Vec<Engine>,
+	seeds: Vec<u64>,
+}
+
+impl VecEnv {
+	pub fn new(config: EngineConfig, num_envs: usize, max_rounds: u32, encoder: EncoderKind) -> Self {
+		let seeds = (0..num_envs as u64)
+			.map(|env_idx| config.random_seed.wrapping_add(env_idx))
+			.collect::<Vec<u64>>();
+		let engines = seeds.iter()
+			.map(|seed| Engine::new(EngineConfig { random_seed: *seed, ..config.clone() }))
+			.collect();
+
+		VecEnv {
+			config,
+			max_rounds,
+			encoder: encoder.build(),
+			engines,
+			seeds,
+		}
+	}
+
+	pub fn num_envs(self: &Self) -> usize {
+		self.engines.len()
+	}
+
+	pub fn observation_shape(self: &Self) -> Vec<usize> {
+		self.encoder.shape(self.config.board_size)
+	}
+
+	pub fn engines(self: &Self) -> &[Engine] {
+		&self.engines
+	}
+
+	pub fn reset(self: &mut Self, seeds: &[u64]) -> Vec<EnvObservation> {
+		assert_eq!(seeds.len(), self.num_envs(), "reset needs one seed per board");
+		self.seeds = seeds.to_vec();
+		for env_idx in 0..self.num_envs() {
+			self.reset_env(env_idx, self.seeds[env_idx]);
+		}
+		self.engines.iter()
+			.map(|engine| self.observe(&engine.current_state))
+			.collect()
+	}
+
+	pub fn step(self: &mut Self, actions: &[(Action, Action)]) -> StepResult {
+		assert_eq!(actions.len(), self.num_envs(), "step needs one pair of actions per board");
+		let mut result = StepResult {
+			observations: Vec::with_capacity(actions.len()),
+			rewards: Vec::with_capacity(actions.len()),
+			terminated: Vec::with_capacity(actions.len()),
+			truncated: Vec::with_capacity(actions.len()),
+			infos: Vec::with_capacity(actions.len()),
+		};
+
+		for (env_idx, env_actions) in actions.iter().enumerate() {
+			let engine = &mut self.engines[env_idx];
+			let mut win_state = engine.apply_move(*env_actions, None);
+			let rewards = engine.game_history.last().unwrap().rewards;
+			let round = engine.current_state.round;
+			let terminated = win_state != WinState::InProgress;
+			let truncated = !terminated && round >= self.max_rounds;
+			if truncated {
+				win_state = engine.truncate();
+			}
+
+			let mut observation = self.observe(&self.engines[env_idx].current_state);
+			let mut final_observation = None;
+			if terminated || truncated {
+				// seeds advance by the number of boards so no two episodes share a layout
+				let seed = self.seeds[env_idx].wrapping_add(self.num_envs() as u64);
+				self.seeds[env_idx] = seed;
+				self.reset_env(env_idx, seed);
+				final_observation = Some(observation);
+				observation = self.observe(&self.engines[env_idx].current_state);
+			}
+
+			result.observations.push(observation);
+			result.rewards.push(rewards);
+			result.terminated.push(terminated);
+			result.truncated.push(truncated);
+			result.infos.push(StepInfo {
+				win_state,
+				round,
+				final_observation,
+			});
+		}
+		result
+	}
+
+	fn reset_env(self: &mut Self, env_idx: usize, seed: u64) {
+		self.engines[env_idx] = Engine::new(EngineConfig {
+			random_seed: seed,
+			..self.config.clone()
+		});
+	}
+
+	fn observe(self: &Self, current_state: &GameState) -> EnvObservation {
+		let observe_player = |idx: PlayerIdx| PlayerObservation {
+			state: self.encoder.encode(current_state, idx),
+			action_mask: GameState::get_action_mask_vec(current_state, idx),
+		};
+		(observe_player(PlayerIdx::Player1), observe_player(PlayerIdx::Player2))
+	}
+}
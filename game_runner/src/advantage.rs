@@ -0,0 +1,56 @@
+use engine::state::{MatchReplay, PlayerIdx};
+
+use crate::error::SurgeError;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AdvantageConfig {
+	pub gamma: f32,
+	pub lambda: f32,
+}
+
+impl AdvantageConfig {
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		if !(0.0..=1.0).contains(&self.gamma) {
+			return Err(SurgeError::InvalidConfig(format!("gamma must be in [0, 1], got {}", self.gamma)));
+		}
+		if !(0.0..=1.0).contains(&self.lambda) {
+			return Err(SurgeError::InvalidConfig(format!("lambda must be in [0, 1], got {}", self.lambda)));
+		}
+		Ok(())
+	}
+}
+
+// GAE(lambda) advantages and returns for one seat of a finished match
+pub fn replay_advantages(replay: &MatchReplay, idx: PlayerIdx, conf: &AdvantageConfig) -> (Vec<f32>, Vec<f32>) {
+	let (rewards, values): (Vec<f32>, Vec<f32>) = replay.sars.iter()
+		.map(|sar| match idx {
+			PlayerIdx::Player1 => (sar.rewards.0, sar.values.0),
+			PlayerIdx::Player2 => (sar.rewards.1, sar.values.1),
+		})
+		.unzip();
+	// a match cut off at the round limit continues past its last step, a finished one does not
+	let bootstrap_value = match idx {
+		PlayerIdx::Player1 => replay.bootstrap_values.0,
+		PlayerIdx::Player2 => replay.bootstrap_values.1,
+	};
+	let bootstrap_value = if replay.truncated() { bootstrap_value } else { 0.0f32 };
+	gae(&rewards, &values, bootstrap_value, conf)
+}
+
+// returns are advantage + value, the discounted return when lambda is 1
+pub fn gae(rewards: &[f32], values: &[f32], bootstrap_value: f32, conf: &AdvantageConfig) -> (Vec<f32>, Vec<f32>) {
+	let mut advantages = vec![0.0f32; rewards.len()];
+	let mut next_value = bootstrap_value;
+	let mut next_advantage = 0.0f32;
+	for step in (0..rewards.len()).rev() {
+		let delta = rewards[step] + conf.gamma * next_value - values[step];
+		next_advantage = delta + conf.gamma * conf.lambda * next_advantage;
+		advantages[step] = next_advantage;
+		next_value = values[step];
+	}
+	let returns = advantages.iter()
+		.zip(values)
+		.map(|(advantage, value)| advantage + value)
+		.collect();
+	(advantages, returns)
+}
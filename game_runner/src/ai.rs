@@ -1,10 +1,61 @@
-use engine::state::{GameState, Action, Direction};
-use rand::seq::{SliceRandom, IteratorRandom};
+use engine::state::{GameState, Action, Direction, FruitType, Player, PlayerIdx, Position};
+use rand::seq::IteratorRandom;
 use rand::rngs::ThreadRng;
 use rand::thread_rng;
 
+use crate::error::SurgeError;
+
+// agent ids reported for matches against the scripted opponents, in ScriptedOpponent::ALL order
+pub const SCRIPTED_AGENT_IDS: [i32; 5] = [69420, 69421, 69422, 69423, 69424];
+
 pub trait AiPlayer {
-	fn get_move(self: &mut Self, current_state: &GameState) -> Action;
+	// picks the move of whoever sits in seat, an error ends the match
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError>;
+}
+
+// the bots a model can be matched against instead of another model
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ScriptedOpponent {
+	Random,
+	Greedy,
+	Contested,
+	Blocker,
+	// tree search, see crate::mcts
+	Mcts,
+}
+
+impl ScriptedOpponent {
+	pub const ALL: [ScriptedOpponent; 5] = [ScriptedOpponent::Random, ScriptedOpponent::Greedy,
+		ScriptedOpponent::Contested, ScriptedOpponent::Blocker, ScriptedOpponent::Mcts];
+
+	pub fn name(self: &Self) -> &'static str {
+		match self {
+			ScriptedOpponent::Random => "random",
+			ScriptedOpponent::Greedy => "greedy",
+			ScriptedOpponent::Contested => "contested",
+			ScriptedOpponent::Blocker => "blocker",
+			ScriptedOpponent::Mcts => "mcts",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<ScriptedOpponent> {
+		ScriptedOpponent::ALL.iter().copied().find(|opponent| opponent.name() == name)
+	}
+
+	pub fn agent_id(self: &Self) -> i32 {
+		SCRIPTED_AGENT_IDS[*self as usize]
+	}
+
+	// None for Mcts, which needs the engine config and is built with MctsPlayer::new
+	pub fn player(self: &Self) -> Option<Box<dyn AiPlayer>> {
+		match self {
+			ScriptedOpponent::Random => Some(Box::new(RandomPlayer::new())),
+			ScriptedOpponent::Greedy => Some(Box::new(GreedyPlayer::new())),
+			ScriptedOpponent::Contested => Some(Box::new(ContestedPlayer::new())),
+			ScriptedOpponent::Blocker => Some(Box::new(BlockerPlayer::new())),
+			ScriptedOpponent::Mcts => None,
+		}
+	}
 }
 
 pub struct RandomPlayer {
@@ -20,16 +71,144 @@ impl RandomPlayer {
 }
 
 impl AiPlayer for RandomPlayer {
-	fn get_move(self: &mut Self, current_state: &GameState) -> Action {
-		let available_actions = current_state
-			.get_valid_moves(&current_state.player2);
-		let available_actions_iter = available_actions
-			.iter()
-			.collect::<Vec<&Action>>();
-		// let action = [Action::Move(Direction::Up),
-		// 	Action::Move(Direction::Down),
-		// 	Action::Move(Direction::Left),
-		// 	Action::Move(Direction::Right)];
-		**available_actions_iter.choose(&mut self.rng).unwrap()
-	}
-}
\ No newline at end of file
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		let (player, _) = seat_players(current_state, seat);
+		Ok(random_move(current_state, player, &mut self.rng))
+	}
+}
+
+// walks to the closest fruit of any kind
+pub struct GreedyPlayer {
+	pub rng: ThreadRng
+}
+
+impl GreedyPlayer {
+	pub fn new() -> Self {
+		GreedyPlayer {
+			rng: thread_rng()
+		}
+	}
+}
+
+impl AiPlayer for GreedyPlayer {
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		let (player, _) = seat_players(current_state, seat);
+		match nearest_fruit(current_state, player.position, |_| true) {
+			Some(target) => Ok(step_towards(current_state, player.position, target)),
+			None => Ok(random_move(current_state, player, &mut self.rng)),
+		}
+	}
+}
+
+// only goes after fruit types whose category is still undecided, the scarcest first,
+// so it doesn't waste moves on categories that are already won or lost
+pub struct ContestedPlayer {
+	pub rng: ThreadRng
+}
+
+impl ContestedPlayer {
+	pub fn new() -> Self {
+		ContestedPlayer {
+			rng: thread_rng()
+		}
+	}
+}
+
+impl AiPlayer for ContestedPlayer {
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		let (player, opponent) = seat_players(current_state, seat);
+		let mut contested = [FruitType::Apple, FruitType::Banana, FruitType::Orange].iter()
+			.copied()
+			.filter(|fruit| {
+				let remaining = *current_state.board.fruit_counts.get(fruit).unwrap_or(&0) as f32;
+				let own = *player.get_fruit_count(*fruit);
+				let their = *opponent.get_fruit_count(*fruit);
+				remaining > 0.0 && own + remaining >= their && their + remaining >= own
+			})
+			.collect::<Vec<FruitType>>();
+		contested.sort_by_key(|fruit| current_state.board.fruit_counts.get(fruit).copied().unwrap_or(0));
+
+		let target = contested.iter()
+			.find_map(|fruit| nearest_fruit(current_state, player.position, |cell| cell == *fruit))
+			.or_else(|| nearest_fruit(current_state, player.position, |_| true));
+		match target {
+			Some(target) => Ok(step_towards(current_state, player.position, target)),
+			None => Ok(random_move(current_state, player, &mut self.rng)),
+		}
+	}
+}
+
+// heads for the fruit the opponent is closest to, taking it first or at least splitting it,
+// and shadows the opponent once the board is empty around them
+pub struct BlockerPlayer {
+	pub rng: ThreadRng
+}
+
+impl BlockerPlayer {
+	pub fn new() -> Self {
+		BlockerPlayer {
+			rng: thread_rng()
+		}
+	}
+}
+
+impl AiPlayer for BlockerPlayer {
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		let (player, opponent) = seat_players(current_state, seat);
+		let target = nearest_fruit(current_state, opponent.position, |_| true)
+			.unwrap_or(opponent.position);
+		if target == player.position {
+			return Ok(random_move(current_state, player, &mut self.rng));
+		}
+		Ok(step_towards(current_state, player.position, target))
+	}
+}
+
+// (the player in seat, their opponent)
+fn seat_players(current_state: &GameState, seat: PlayerIdx) -> (&Player, &Player) {
+	match seat {
+		PlayerIdx::Player1 => (&current_state.player1, &current_state.player2),
+		PlayerIdx::Player2 => (&current_state.player2, &current_state.player1),
+	}
+}
+
+fn random_move(current_state: &GameState, player: &Player, rng: &mut ThreadRng) -> Action {
+	current_state.get_valid_moves(player)
+		.into_iter()
+		.choose(rng)
+		.unwrap_or(Action::DoNothing)
+}
+
+fn distance(from: Position, to: Position) -> i32 {
+	(from.x as i32 - to.x as i32).abs() + (from.y as i32 - to.y as i32).abs()
+}
+
+// closest cell holding a fruit that passes wanted, ties go to the first cell in x, y order
+fn nearest_fruit<F: Fn(FruitType) -> bool>(current_state: &GameState, from: Position, wanted: F) -> Option<Position> {
+	current_state.board.fruit_map.indexed_iter()
+		.filter_map(|((x, y), cell)| match cell {
+			Some(fruit) if wanted(*fruit) => Some(Position::new(x as i8, y as i8)),
+			_ => None,
+		})
+		.min_by_key(|position| distance(from, *position))
+}
+
+// the valid move that gets closest to target, closing the longer axis first
+fn step_towards(current_state: &GameState, from: Position, target: Position) -> Action {
+	let mut directions = vec![];
+	let (dx, dy) = (target.x as i32 - from.x as i32, target.y as i32 - from.y as i32);
+	let horizontal = if dx > 0 { Some(Direction::Right) } else if dx < 0 { Some(Direction::Left) } else { None };
+	let vertical = if dy > 0 { Some(Direction::Down) } else if dy < 0 { Some(Direction::Up) } else { None };
+	if dx.abs() >= dy.abs() {
+		directions.extend(horizontal.into_iter().chain(vertical));
+	} else {
+		directions.extend(vertical.into_iter().chain(horizontal));
+	}
+	directions.into_iter()
+		.map(Action::Move)
+		.find(|action| match action {
+			Action::Move(direction) => !GameState::outside_bounds(current_state.board.size, &(direction.as_pos() + from)),
+			Action::DoNothing => true,
+		})
+		.unwrap_or(Action::DoNothing)
+}
@@ -0,0 +1,33 @@
+use std::error::Error;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SurgeError {
+	ModelNotFound(i32),
+	ModelLoad(i32, String),
+	InvalidConfig(String),
+	Inference(i32, String),
+	EngineInvariant(String),
+	Worker(String),
+	JobNotFound(u64),
+	QueueFull(usize),
+	LeagueAgentNotFound(i32),
+}
+
+impl fmt::Display for SurgeError {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			SurgeError::ModelNotFound(id) => write!(f, "model {} not found in the model store", id),
+			SurgeError::ModelLoad(id, reason) => write!(f, "failed to load model {}: {}", id, reason),
+			SurgeError::InvalidConfig(reason) => write!(f, "invalid rollout config: {}", reason),
+			SurgeError::Inference(id, reason) => write!(f, "inference failed for model {}: {}", id, reason),
+			SurgeError::EngineInvariant(reason) => write!(f, "engine invariant violated: {}", reason),
+			SurgeError::Worker(reason) => write!(f, "rollout worker failed: {}", reason),
+			SurgeError::JobNotFound(id) => write!(f, "rollout job {} not found", id),
+			SurgeError::QueueFull(capacity) => write!(f, "rollout job queue is full ({} jobs waiting)", capacity),
+			SurgeError::LeagueAgentNotFound(id) => write!(f, "agent {} is not in the league", id),
+		}
+	}
+}
+
+impl Error for SurgeError {}
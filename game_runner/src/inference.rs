@@ -0,0 +1,217 @@
+use std::convert::TryInto;
+use std::path::Path;
+use std::sync::Arc;
+
+use serde_json::{json, Value};
+
+use crate::mlp::MlpBackend;
+
+// every output tensor of a model flattened to one row per state, [output][state][values]
+pub type ModelOutputs = Vec<Vec<Vec<f32>>>;
+
+// runs a model on a batch of encoded states, how its outputs are read is up to the model's OutputSpec.
+// An empty batch runs nothing and has no outputs.
+pub trait InferenceBackend: Send + Sync {
+	fn forward(self: &Self, states: &[Vec<f32>]) -> Result<ModelOutputs, String>;
+}
+
+// logits of Up, Down, Left, Right and optionally DoNothing
+pub const MOVE_LOGITS: usize = 4;
+pub const ALL_LOGITS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputLayout {
+	// a single [batch, logits (+ value)] output
+	Concatenated,
+	// a [batch, logits] output followed by a [batch, 1] value output when the model has a value head
+	Tuple,
+}
+
+// how the outputs of a model map to policy logits and a value estimate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OutputSpec {
+	pub layout: OutputLayout,
+	// MOVE_LOGITS, or ALL_LOGITS when the policy can also pick DoNothing
+	pub num_logits: usize,
+	// without one the value of every state is reported as 0
+	pub value_head: bool,
+}
+
+impl Default for OutputSpec {
+	fn default() -> Self {
+		OutputSpec {
+			layout: OutputLayout::Concatenated,
+			num_logits: MOVE_LOGITS,
+			value_head: true,
+		}
+	}
+}
+
+impl OutputSpec {
+	pub fn validate(self: &Self) -> Result<(), String> {
+		if self.num_logits != MOVE_LOGITS && self.num_logits != ALL_LOGITS {
+			return Err(format!("models must output {} or {} logits, got {}", MOVE_LOGITS, ALL_LOGITS, self.num_logits));
+		}
+		Ok(())
+	}
+
+	pub fn to_json(self: &Self) -> String {
+		let layout = match self.layout {
+			OutputLayout::Concatenated => "concatenated",
+			OutputLayout::Tuple => "tuple",
+		};
+		json!({"layout": layout, "num_logits": self.num_logits, "value_head": self.value_head}).to_string()
+	}
+
+	pub fn from_json(bytes: &[u8]) -> Result<OutputSpec, String> {
+		let spec: Value = serde_json::from_slice(bytes).map_err(|err| err.to_string())?;
+		let layout = match spec["layout"].as_str() {
+			Some("concatenated") => OutputLayout::Concatenated,
+			Some("tuple") => OutputLayout::Tuple,
+			other => return Err(format!("unknown output layout {:?}", other)),
+		};
+		let spec = OutputSpec {
+			layout,
+			num_logits: spec["num_logits"].as_u64().ok_or_else(|| "output spec has no num_logits".to_string())? as usize,
+			value_head: spec["value_head"].as_bool().ok_or_else(|| "output spec has no value_head".to_string())?,
+		};
+		spec.validate()?;
+		Ok(spec)
+	}
+
+	// splits the outputs of a model into the logits and value of every state
+	pub fn read(self: &Self, outputs: &ModelOutputs, batch_size: usize) -> Result<Vec<(Vec<f32>, f32)>, String> {
+		let value_len = if self.value_head { 1 } else { 0 };
+		let expected_outputs = match self.layout {
+			OutputLayout::Concatenated => 1,
+			OutputLayout::Tuple => 1 + value_len,
+		};
+		if outputs.len() != expected_outputs {
+			return Err(format!("expected {} model outputs, got {}", expected_outputs, outputs.len()));
+		}
+		if let Some(output) = outputs.iter().find(|output| output.len() != batch_size) {
+			return Err(format!("expected {} rows per output, got {}", batch_size, output.len()));
+		}
+
+		(0..batch_size)
+			.map(|state_idx| {
+				let row = outputs.iter()
+					.flat_map(|output| output[state_idx].iter().copied())
+					.collect::<Vec<f32>>();
+				let logits_len = match self.layout {
+					OutputLayout::Concatenated => row.len().min(self.num_logits),
+					OutputLayout::Tuple => outputs[0][state_idx].len(),
+				};
+				if logits_len != self.num_logits || row.len() != self.num_logits + value_len {
+					return Err(format!("expected {} logits and {} values per state, got {} outputs",
+									   self.num_logits, value_len, row.len()));
+				}
+				Ok((row[..self.num_logits].to_vec(), row.get(self.num_logits).copied().unwrap_or(0.0f32)))
+			})
+			.collect()
+	}
+}
+
+// a loaded model along with the spec its outputs are read by
+pub struct Policy {
+	backend: Arc<dyn InferenceBackend>,
+	output_spec: OutputSpec,
+}
+
+impl Policy {
+	pub fn new(backend: Arc<dyn InferenceBackend>, output_spec: OutputSpec) -> Self {
+		Policy {
+			backend,
+			output_spec,
+		}
+	}
+
+	pub fn output_spec(self: &Self) -> OutputSpec {
+		self.output_spec
+	}
+
+	// unmasked logits and value estimate of every state
+	pub fn evaluate(self: &Self, states: &[Vec<f32>]) -> Result<Vec<(Vec<f32>, f32)>, String> {
+		if states.is_empty() {
+			return Ok(vec![]);
+		}
+		let outputs = self.backend.forward(states)?;
+		self.output_spec.read(&outputs, states.len())
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ModelFormat {
+	// TorchScript module, needs the torch feature
+	TorchScript,
+	// MLP weights in a safetensors file, run by the native backend
+	SafeTensors,
+	// ONNX graph, needs the onnx feature
+	Onnx,
+}
+
+impl ModelFormat {
+	pub const ALL: [ModelFormat; 3] = [ModelFormat::TorchScript, ModelFormat::SafeTensors, ModelFormat::Onnx];
+
+	pub fn extension(self: &Self) -> &'static str {
+		match self {
+			ModelFormat::TorchScript => "pt",
+			ModelFormat::SafeTensors => "safetensors",
+			ModelFormat::Onnx => "onnx",
+		}
+	}
+
+	pub fn from_extension(extension: &str) -> Option<ModelFormat> {
+		ModelFormat::ALL.iter().copied().find(|format| format.extension() == extension)
+	}
+
+	// guesses the format of an uploaded file from its first bytes
+	pub fn detect(bytes: &[u8]) -> Option<ModelFormat> {
+		// TorchScript modules are zip archives
+		if bytes.starts_with(b"PK\x03\x04") {
+			return Some(ModelFormat::TorchScript);
+		}
+		// safetensors start with the length of a JSON header
+		if bytes.len() > 8 {
+			let header_len = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+			if bytes[8] == b'{' && header_len <= (bytes.len() - 8) as u64 {
+				return Some(ModelFormat::SafeTensors);
+			}
+		}
+		// an ONNX ModelProto opens with its small ir_version varint (protobuf field 1) followed by the tag of
+		// producer_name, producer_version, domain, model_version, doc_string, graph or opset_import
+		const ONNX_FIELD_TAGS: [u8; 7] = [0x12, 0x1a, 0x22, 0x28, 0x32, 0x3a, 0x42];
+		match bytes {
+			[0x08, ir_version, tag, ..] if (1..=0x20).contains(ir_version) && ONNX_FIELD_TAGS.contains(tag) => Some(ModelFormat::Onnx),
+			_ => None,
+		}
+	}
+}
+
+pub fn load_backend(format: ModelFormat, path: &Path) -> Result<Arc<dyn InferenceBackend>, String> {
+	match format {
+		ModelFormat::TorchScript => load_torchscript(path),
+		ModelFormat::SafeTensors => Ok(Arc::new(MlpBackend::load(path)?)),
+		ModelFormat::Onnx => load_onnx(path),
+	}
+}
+
+#[cfg(feature = "torch")]
+fn load_torchscript(path: &Path) -> Result<Arc<dyn InferenceBackend>, String> {
+	Ok(Arc::new(crate::torchscript::TorchScriptBackend::load(path)?))
+}
+
+#[cfg(not(feature = "torch"))]
+fn load_torchscript(_path: &Path) -> Result<Arc<dyn InferenceBackend>, String> {
+	Err("TorchScript models need game_runner to be built with the torch feature".to_string())
+}
+
+#[cfg(feature = "onnx")]
+fn load_onnx(path: &Path) -> Result<Arc<dyn InferenceBackend>, String> {
+	Ok(Arc::new(crate::onnx::OnnxBackend::load(path)?))
+}
+
+#[cfg(not(feature = "onnx"))]
+fn load_onnx(_path: &Path) -> Result<Arc<dyn InferenceBackend>, String> {
+	Err("ONNX models need game_runner to be built with the onnx feature".to_string())
+}
@@ -0,0 +1,187 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::thread;
+
+use engine::state::MatchReplay;
+
+use crate::error::SurgeError;
+use crate::manager::RolloutManager;
+
+pub type JobId = u64;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum JobState {
+	Queued,
+	Running,
+	Completed,
+	Failed,
+	Cancelled,
+}
+
+#[derive(Debug, Clone)]
+pub struct JobStatus {
+	pub job_id: JobId,
+	pub state: JobState,
+	// matches finished so far out of total
+	pub completed: u32,
+	pub total: u32,
+	pub error: Option<SurgeError>,
+}
+
+impl JobStatus {
+	pub fn finished(self: &Self) -> bool {
+		match self.state {
+			JobState::Queued | JobState::Running => false,
+			JobState::Completed | JobState::Failed | JobState::Cancelled => true,
+		}
+	}
+}
+
+struct Job {
+	status: JobStatus,
+	// replays that have not been fetched yet
+	results: Vec<MatchReplay>,
+	stop: Arc<AtomicBool>,
+}
+
+type JobTable = Arc<Mutex<HashMap<JobId, Job>>>;
+
+// rollouts submitted by id and played on a fixed set of runner threads, at most capacity jobs wait for a runner
+pub struct JobQueue {
+	jobs: JobTable,
+	queue: SyncSender<(JobId, RolloutManager)>,
+	capacity: usize,
+	next_id: AtomicU64,
+}
+
+impl JobQueue {
+	pub fn new(num_runners: usize, capacity: usize) -> Result<JobQueue, SurgeError> {
+		let jobs: JobTable = Arc::new(Mutex::new(HashMap::new()));
+		let (queue, pending) = mpsc::sync_channel(capacity);
+		let pending = Arc::new(Mutex::new(pending));
+		for runner_idx in 0..num_runners.max(1) {
+			let jobs = jobs.clone();
+			let pending = pending.clone();
+			thread::Builder::new()
+				.name(format!("rollout-job-{}", runner_idx))
+				.spawn(move || JobQueue::run_jobs(jobs, pending))
+				.map_err(|err| SurgeError::Worker(format!("failed to spawn rollout job runner: {}", err)))?;
+		}
+
+		Ok(JobQueue {
+			jobs,
+			queue,
+			capacity,
+			next_id: AtomicU64::new(1),
+		})
+	}
+
+	pub fn submit(self: &Self, manager: RolloutManager) -> Result<JobStatus, SurgeError> {
+		manager.validate()?;
+		let job_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+		let status = JobStatus {
+			job_id,
+			state: JobState::Queued,
+			completed: 0,
+			total: manager.config().max_matches,
+			error: None,
+		};
+		// registered before it is queued so a runner never picks up a job it cannot find
+		self.jobs.lock().unwrap().insert(job_id, Job {
+			status: status.clone(),
+			results: vec![],
+			stop: Arc::new(AtomicBool::new(false)),
+		});
+		match self.queue.try_send((job_id, manager)) {
+			Ok(()) => Ok(status),
+			Err(err) => {
+				self.jobs.lock().unwrap().remove(&job_id);
+				match err {
+					TrySendError::Full(_) => Err(SurgeError::QueueFull(self.capacity)),
+					TrySendError::Disconnected(_) => Err(SurgeError::Worker("rollout job runners have stopped".to_string())),
+				}
+			}
+		}
+	}
+
+	pub fn status(self: &Self, job_id: JobId) -> Result<JobStatus, SurgeError> {
+		let jobs = self.jobs.lock().unwrap();
+		let job = jobs.get(&job_id).ok_or(SurgeError::JobNotFound(job_id))?;
+		Ok(job.status.clone())
+	}
+
+	// hands over the replays finished since the last fetch, a finished job is forgotten once it has none left
+	pub fn fetch_results(self: &Self, job_id: JobId) -> Result<(JobStatus, Vec<MatchReplay>), SurgeError> {
+		let mut jobs = self.jobs.lock().unwrap();
+		let job = jobs.get_mut(&job_id).ok_or(SurgeError::JobNotFound(job_id))?;
+		let results = std::mem::take(&mut job.results);
+		let status = job.status.clone();
+		if status.finished() {
+			jobs.remove(&job_id);
+		}
+		Ok((status, results))
+	}
+
+	// a queued job is dropped before it starts, a running one stops after the matches in flight
+	pub fn cancel(self: &Self, job_id: JobId) -> Result<JobStatus, SurgeError> {
+		let mut jobs = self.jobs.lock().unwrap();
+		let job = jobs.get_mut(&job_id).ok_or(SurgeError::JobNotFound(job_id))?;
+		job.stop.store(true, Ordering::Relaxed);
+		if job.status.state == JobState::Queued {
+			job.status.state = JobState::Cancelled;
+		}
+		Ok(job.status.clone())
+	}
+
+	fn run_jobs(jobs: JobTable, pending: Arc<Mutex<Receiver<(JobId, RolloutManager)>>>) {
+		loop {
+			// the lock is only held while waiting, so one idle runner takes the next job
+			let next = pending.lock().unwrap().recv();
+			let (job_id, manager) = match next {
+				Ok(job) => job,
+				Err(_) => return,
+			};
+			let stop = match jobs.lock().unwrap().get_mut(&job_id) {
+				Some(job) if job.status.state == JobState::Queued => {
+					job.status.state = JobState::Running;
+					job.stop.clone()
+				}
+				_ => continue,
+			};
+			let result = JobQueue::run_job(&jobs, job_id, manager, stop.clone());
+
+			if let Some(job) = jobs.lock().unwrap().get_mut(&job_id) {
+				match result {
+					Err(err) => {
+						job.status.state = JobState::Failed;
+						job.status.error = Some(err);
+					}
+					// a cancel that comes in after the last match leaves nothing to cancel
+					Ok(()) if stop.load(Ordering::Relaxed) && job.status.completed < job.status.total => {
+						job.status.state = JobState::Cancelled
+					}
+					Ok(()) => job.status.state = JobState::Completed,
+				}
+			}
+		}
+	}
+
+	fn run_job(jobs: &JobTable, job_id: JobId, manager: RolloutManager, stop: Arc<AtomicBool>) -> Result<(), SurgeError> {
+		let (sink, replays) = mpsc::channel();
+		let rollout = thread::Builder::new()
+			.name(format!("rollout-job-{}-manager", job_id))
+			.spawn(move || manager.stream_matches(sink, stop))
+			.map_err(|err| SurgeError::Worker(format!("failed to spawn rollout job: {}", err)))?;
+
+		for replay in replays.iter() {
+			if let Some(job) = jobs.lock().unwrap().get_mut(&job_id) {
+				job.status.completed += 1;
+				job.results.push(replay);
+			}
+		}
+		rollout.join()
+			.map_err(|_| SurgeError::Worker("rollout job thread panicked".to_string()))?
+	}
+}
@@ -0,0 +1,33 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde_json::Value;
+
+// reads the JSON saved at path with parse, or starts from empty when nothing has been saved yet
+pub fn load<T, P, E>(path: &Path, parse: P, empty: E) -> io::Result<T>
+	where P: FnOnce(&[u8]) -> Result<T, String>, E: FnOnce() -> T {
+	if !path.exists() {
+		return Ok(empty());
+	}
+	parse(&fs::read(path)?)
+		.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err)))
+}
+
+// written next to path and renamed over it, so a crash mid-write never leaves half a file behind
+pub fn save(path: &Path, json: String) -> io::Result<()> {
+	if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+		fs::create_dir_all(parent)?;
+	}
+	let tmp_path = path.with_extension("json.tmp");
+	fs::write(&tmp_path, json)?;
+	fs::rename(&tmp_path, path)
+}
+
+pub fn int(entry: &Value, field: &str) -> Result<i64, String> {
+	entry[field].as_i64().ok_or_else(|| format!("entry has no {}", field))
+}
+
+pub fn float(entry: &Value, field: &str) -> Result<f64, String> {
+	entry[field].as_f64().ok_or_else(|| format!("entry has no {}", field))
+}
@@ -0,0 +1,365 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use engine::state::{MatchReplay, PlayerWinner, WinState};
+
+use crate::error::SurgeError;
+use crate::json_store;
+use crate::matchmaking::MatchmakingKind;
+use crate::model_store::ModelStore;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AgentRole {
+	// plays itself and every snapshot in the league, mostly the ones it loses to
+	Main,
+	// plays the current main agents to find their weaknesses
+	MainExploiter,
+	// plays every snapshot to find weaknesses of the league as a whole
+	LeagueExploiter,
+}
+
+impl AgentRole {
+	pub const ALL: [AgentRole; 3] = [AgentRole::Main, AgentRole::MainExploiter, AgentRole::LeagueExploiter];
+
+	pub fn name(self: &Self) -> &'static str {
+		match self {
+			AgentRole::Main => "main",
+			AgentRole::MainExploiter => "main_exploiter",
+			AgentRole::LeagueExploiter => "league_exploiter",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<AgentRole> {
+		AgentRole::ALL.iter().find(|role| role.name() == name).copied()
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LeagueConfig {
+	// an agent is frozen once it scores this much over its matches since the last snapshot, draws count half
+	pub snapshot_win_rate: f32,
+	// matches since the last snapshot before the win rate is trusted
+	pub min_matches: u32,
+	// an agent is frozen after this many matches, self-play included, whatever its win rate
+	pub max_matches: u32,
+	// snapshot ids count up from here, agents can't be added at or above it
+	pub first_snapshot_id: i32,
+}
+
+impl Default for LeagueConfig {
+	fn default() -> Self {
+		LeagueConfig {
+			snapshot_win_rate: 0.7,
+			min_matches: 200,
+			max_matches: 2000,
+			first_snapshot_id: 1_000_000,
+		}
+	}
+}
+
+impl LeagueConfig {
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		if !(self.snapshot_win_rate > 0.0 && self.snapshot_win_rate <= 1.0) {
+			return Err(SurgeError::InvalidConfig(format!("snapshot_win_rate must be within (0, 1], got {}", self.snapshot_win_rate)));
+		}
+		if self.max_matches == 0 || self.min_matches > self.max_matches {
+			return Err(SurgeError::InvalidConfig(format!(
+				"max_matches must be positive and at least min_matches, got {} and {}", self.max_matches, self.min_matches)));
+		}
+		Ok(())
+	}
+}
+
+// an agent the trainer keeps updating, its model is stored under agent_id
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeagueAgent {
+	pub agent_id: i32,
+	pub role: AgentRole,
+	// snapshots frozen from this agent, oldest first
+	pub snapshots: Vec<i32>,
+	// matches against other agents and their score since the last snapshot
+	pub matches: u32,
+	pub score: f32,
+	// every match since the last snapshot, self-play included, for the max_matches fallback
+	pub played: u32,
+}
+
+impl LeagueAgent {
+	pub fn win_rate(self: &Self) -> f32 {
+		if self.matches == 0 {
+			0.0f32
+		} else {
+			self.score / self.matches as f32
+		}
+	}
+}
+
+// frozen copy of an agent's model, stored under snapshot_id and never trained again
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Snapshot {
+	pub snapshot_id: i32,
+	pub agent_id: i32,
+	pub role: AgentRole,
+}
+
+// opponent pool of an agent's next rollout and how to pick from it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Matchup {
+	pub opponent_ids: Vec<i32>,
+	pub matchmaking: MatchmakingKind,
+}
+
+// Learning agents by role and the snapshots frozen from them, following AlphaStar's league.
+// Main agents play themselves and the whole pool with PFSP, main exploiters play the current
+// main agents and league exploiters play the whole pool with PFSP.
+pub struct League {
+	conf: LeagueConfig,
+	agents: BTreeMap<i32, LeagueAgent>,
+	snapshots: Vec<Snapshot>,
+	next_snapshot_id: i32,
+}
+
+impl League {
+	pub fn new(conf: LeagueConfig) -> Self {
+		League {
+			conf,
+			agents: BTreeMap::new(),
+			snapshots: Vec::new(),
+			next_snapshot_id: conf.first_snapshot_id,
+		}
+	}
+
+	pub fn config(self: &Self) -> &LeagueConfig {
+		&self.conf
+	}
+
+	pub fn add_agent(self: &mut Self, agent_id: i32, role: AgentRole) -> Result<LeagueAgent, SurgeError> {
+		if agent_id >= self.conf.first_snapshot_id {
+			return Err(SurgeError::InvalidConfig(format!(
+				"agent ids from {} on are kept for snapshots, got {}", self.conf.first_snapshot_id, agent_id)));
+		}
+		if self.agents.contains_key(&agent_id) {
+			return Err(SurgeError::InvalidConfig(format!("agent {} is already in the league", agent_id)));
+		}
+		let agent = LeagueAgent {
+			agent_id,
+			role,
+			snapshots: vec![],
+			matches: 0,
+			score: 0.0f32,
+			played: 0,
+		};
+		self.agents.insert(agent_id, agent.clone());
+		Ok(agent)
+	}
+
+	// the agent's snapshots stay in the pool
+	pub fn remove_agent(self: &mut Self, agent_id: i32) -> Result<LeagueAgent, SurgeError> {
+		self.agents.remove(&agent_id).ok_or(SurgeError::LeagueAgentNotFound(agent_id))
+	}
+
+	pub fn agent(self: &Self, agent_id: i32) -> Result<&LeagueAgent, SurgeError> {
+		self.agents.get(&agent_id).ok_or(SurgeError::LeagueAgentNotFound(agent_id))
+	}
+
+	// every learning agent by id
+	pub fn agents(self: &Self) -> Vec<LeagueAgent> {
+		self.agents.values().cloned().collect()
+	}
+
+	// oldest first
+	pub fn snapshots(self: &Self) -> &[Snapshot] {
+		&self.snapshots
+	}
+
+	pub fn next_snapshot_id(self: &Self) -> i32 {
+		self.next_snapshot_id
+	}
+
+	pub fn matchup(self: &Self, agent_id: i32) -> Result<Matchup, SurgeError> {
+		let snapshot_ids = self.snapshots.iter().map(|snapshot| snapshot.snapshot_id).collect::<Vec<i32>>();
+		let main_ids = self.agents.values()
+			.filter(|agent| agent.role == AgentRole::Main)
+			.map(|agent| agent.agent_id)
+			.collect::<Vec<i32>>();
+		let pfsp = MatchmakingKind::Pfsp { exponent: MatchmakingKind::DEFAULT_PFSP_EXPONENT };
+		let (opponent_ids, matchmaking) = match self.agent(agent_id)?.role {
+			AgentRole::Main => ([vec![agent_id], snapshot_ids].concat(), pfsp),
+			AgentRole::MainExploiter => (main_ids, MatchmakingKind::Uniform),
+			// the main agents stand in for the pool until the first snapshot
+			AgentRole::LeagueExploiter if snapshot_ids.is_empty() => (main_ids, pfsp),
+			AgentRole::LeagueExploiter => (snapshot_ids, pfsp),
+		};
+		if opponent_ids.is_empty() {
+			return Err(SurgeError::InvalidConfig(format!("the league has no opponents for agent {} yet", agent_id)));
+		}
+		Ok(Matchup {
+			opponent_ids,
+			matchmaking,
+		})
+	}
+
+	// counts agent_id's finished matches towards its next snapshot, self-play only counts towards max_matches
+	pub fn record(self: &mut Self, agent_id: i32, replays: &[MatchReplay]) -> Result<(), SurgeError> {
+		let agent = self.agents.get_mut(&agent_id).ok_or(SurgeError::LeagueAgentNotFound(agent_id))?;
+		for replay in replays {
+			let (id1, id2) = replay.agent_ids;
+			if id1 != agent_id && id2 != agent_id {
+				continue;
+			}
+			let score = match replay.outcome {
+				WinState::InProgress => continue,
+				WinState::Finished(PlayerWinner::Player1) => 1.0f32,
+				WinState::Finished(PlayerWinner::Player2) => 0.0f32,
+				WinState::Draw | WinState::Timeout => 0.5f32,
+			};
+			agent.played += 1;
+			if id1 == id2 {
+				continue;
+			}
+			agent.matches += 1;
+			agent.score += if id1 == agent_id { score } else { 1.0f32 - score };
+		}
+		Ok(())
+	}
+
+	pub fn snapshot_due(self: &Self, agent_id: i32) -> Result<bool, SurgeError> {
+		let agent = self.agent(agent_id)?;
+		Ok(agent.played >= self.conf.max_matches
+			|| (agent.matches >= self.conf.min_matches && agent.win_rate() >= self.conf.snapshot_win_rate))
+	}
+
+	// adds the agent to the pool under next_snapshot_id, its model has to be copied there by the caller
+	pub fn freeze(self: &mut Self, agent_id: i32) -> Result<Snapshot, SurgeError> {
+		let snapshot_id = self.next_snapshot_id;
+		let agent = self.agents.get_mut(&agent_id).ok_or(SurgeError::LeagueAgentNotFound(agent_id))?;
+		let snapshot = Snapshot {
+			snapshot_id,
+			agent_id,
+			role: agent.role,
+		};
+		agent.snapshots.push(snapshot_id);
+		agent.matches = 0;
+		agent.score = 0.0f32;
+		agent.played = 0;
+		self.snapshots.push(snapshot);
+		self.next_snapshot_id += 1;
+		Ok(snapshot)
+	}
+
+	pub fn to_json(self: &Self) -> String {
+		let agents = self.agents.values()
+			.map(|agent| json!({
+				"agent_id": agent.agent_id, "role": agent.role.name(), "snapshots": agent.snapshots,
+				"matches": agent.matches, "score": agent.score, "played": agent.played,
+			}))
+			.collect::<Vec<Value>>();
+		let snapshots = self.snapshots.iter()
+			.map(|snapshot| json!({
+				"snapshot_id": snapshot.snapshot_id, "agent_id": snapshot.agent_id, "role": snapshot.role.name(),
+			}))
+			.collect::<Vec<Value>>();
+		json!({"next_snapshot_id": self.next_snapshot_id, "agents": agents, "snapshots": snapshots}).to_string()
+	}
+
+	pub fn from_json(bytes: &[u8], conf: LeagueConfig) -> Result<League, String> {
+		let league: Value = serde_json::from_slice(bytes).map_err(|err| err.to_string())?;
+		let role = |value: &Value| value["role"].as_str()
+			.and_then(AgentRole::from_name)
+			.ok_or_else(|| format!("league entry has an unknown role {}", value["role"]));
+
+		let mut agents = BTreeMap::new();
+		for entry in league["agents"].as_array().cloned().unwrap_or_default() {
+			let snapshots = entry["snapshots"].as_array().cloned().unwrap_or_default()
+				.iter()
+				.map(|id| id.as_i64().map(|id| id as i32).ok_or_else(|| "snapshot is not an id".to_string()))
+				.collect::<Result<Vec<i32>, String>>()?;
+			let agent = LeagueAgent {
+				agent_id: json_store::int(&entry, "agent_id")? as i32,
+				role: role(&entry)?,
+				snapshots,
+				matches: json_store::int(&entry, "matches")? as u32,
+				score: json_store::float(&entry, "score")? as f32,
+				played: json_store::int(&entry, "played")? as u32,
+			};
+			agents.insert(agent.agent_id, agent);
+		}
+		let mut snapshots = vec![];
+		for entry in league["snapshots"].as_array().cloned().unwrap_or_default() {
+			snapshots.push(Snapshot {
+				snapshot_id: json_store::int(&entry, "snapshot_id")? as i32,
+				agent_id: json_store::int(&entry, "agent_id")? as i32,
+				role: role(&entry)?,
+			});
+		}
+		Ok(League {
+			conf,
+			agents,
+			snapshots,
+			next_snapshot_id: json_store::int(&league, "next_snapshot_id")? as i32,
+		})
+	}
+}
+
+// League shared by every request and persisted as JSON at path
+pub struct LeagueStore {
+	path: PathBuf,
+	league: Mutex<League>,
+}
+
+impl LeagueStore {
+	pub fn open<P: AsRef<Path>>(path: P, conf: LeagueConfig) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let league = json_store::load(&path, |bytes| League::from_json(bytes, conf), || League::new(conf))?;
+		Ok(LeagueStore {
+			path,
+			league: Mutex::new(league),
+		})
+	}
+
+	pub fn add_agent(self: &Self, agent_id: i32, role: AgentRole) -> Result<LeagueAgent, SurgeError> {
+		self.league.lock().unwrap().add_agent(agent_id, role)
+	}
+
+	pub fn remove_agent(self: &Self, agent_id: i32) -> Result<LeagueAgent, SurgeError> {
+		self.league.lock().unwrap().remove_agent(agent_id)
+	}
+
+	pub fn agents(self: &Self) -> Vec<LeagueAgent> {
+		self.league.lock().unwrap().agents()
+	}
+
+	pub fn snapshots(self: &Self) -> Vec<Snapshot> {
+		self.league.lock().unwrap().snapshots().to_vec()
+	}
+
+	pub fn matchup(self: &Self, agent_id: i32) -> Result<Matchup, SurgeError> {
+		self.league.lock().unwrap().matchup(agent_id)
+	}
+
+	// records a finished rollout of agent_id and freezes it into the pool if it is due,
+	// copying its current model in the store to the snapshot's id
+	pub fn record_rollout(self: &Self, agent_id: i32, replays: &[MatchReplay], model_store: &ModelStore) -> Result<Option<Snapshot>, SurgeError> {
+		let mut league = self.league.lock().unwrap();
+		league.record(agent_id, replays)?;
+		if !league.snapshot_due(agent_id)? {
+			return Ok(None);
+		}
+		let snapshot_id = league.next_snapshot_id();
+		model_store.copy_model(agent_id, snapshot_id)
+			.map_err(|err| match err.kind() {
+				io::ErrorKind::NotFound => SurgeError::ModelNotFound(agent_id),
+				_ => SurgeError::ModelLoad(agent_id, format!("failed to copy it to snapshot {}: {}", snapshot_id, err)),
+			})?;
+		league.freeze(agent_id).map(Some)
+	}
+
+	pub fn save(self: &Self) -> io::Result<()> {
+		let json = self.league.lock().unwrap().to_json();
+		json_store::save(&self.path, json)
+	}
+}
@@ -6,6 +6,22 @@ use engine::engine::{Engine, EngineConfig};
 use worker::RolloutWorker;
 
 mod tests;
+pub mod advantage;
+pub mod ai;
+pub mod error;
+pub mod inference;
+pub mod jobs;
+pub mod json_store;
+pub mod league;
 pub mod manager;
+pub mod matchmaking;
+pub mod mcts;
+pub mod mlp;
+pub mod model_store;
+pub mod ratings;
+pub mod solver;
 pub mod worker;
-mod ai;
\ No newline at end of file
+#[cfg(feature = "onnx")]
+mod onnx;
+#[cfg(feature = "torch")]
+mod torchscript;
\ No newline at end of file
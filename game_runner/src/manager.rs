@@ -1,20 +1,187 @@
-use rand::rngs::ThreadRng;
-use rand::seq::SliceRandom;
+use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::Sender;
+use std::thread;
 
 use engine::engine::EngineConfig;
+use engine::observation::EncoderKind;
+use engine::state::MatchReplay;
 
+use crate::ai::ScriptedOpponent;
+use crate::error::SurgeError;
+use crate::matchmaking::MatchmakingKind;
+use crate::mcts::MctsConfig;
+use crate::model_store::ModelStore;
+use crate::ratings::RatingStore;
 use crate::worker::RolloutWorker;
 
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MatchMode {
+	// every match is against the scripted opponent, RandomPlayer unless configured otherwise
+	VsRandom,
+	VsPool,
+	// fraction of matches played against the opponent pool, the rest are against the scripted opponent
+	Mixed(f32),
+}
+
 #[derive(Clone)]
 pub struct RolloutConfig {
 	pub engine_config: EngineConfig,
 	pub agent_ids: (i32, Vec<i32>),
 	pub max_rounds: u32,
 	pub evaluation_mode: bool,
-	pub max_matches: u32
+	pub max_matches: u32,
+	pub match_mode: MatchMode,
+	// how opponents are picked from the pool
+	pub matchmaking: MatchmakingKind,
+	// bot played in the matches that are not against the pool
+	pub scripted_opponent: ScriptedOpponent,
+	// search settings when the scripted opponent is Mcts
+	pub mcts: MctsConfig,
+	pub observation: EncoderKind,
+	// number of matches each worker steps in lockstep for batched inference, 1 plays them one by one
+	pub batch_size: u32,
+	// keep the masked policy logits of every step in the replay
+	pub record_logits: bool
+}
+
+impl RolloutConfig {
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		self.engine_config.validate().map_err(SurgeError::InvalidConfig)?;
+		self.observation.validate(self.engine_config.board_size).map_err(SurgeError::InvalidConfig)?;
+		if self.max_rounds == 0 {
+			return Err(SurgeError::InvalidConfig("max_rounds must be positive".to_string()));
+		}
+		if self.batch_size == 0 {
+			return Err(SurgeError::InvalidConfig("batch_size must be positive".to_string()));
+		}
+		self.matchmaking.validate()?;
+		if self.scripted_opponent == ScriptedOpponent::Mcts {
+			self.mcts.validate()?;
+		}
+		match self.match_mode {
+			MatchMode::VsRandom => {}
+			MatchMode::VsPool | MatchMode::Mixed(_) if self.agent_ids.1.is_empty() => {
+				return Err(SurgeError::InvalidConfig("matches against the pool need at least one opponent id".to_string()));
+			}
+			MatchMode::Mixed(pool_ratio) if !(0.0..=1.0).contains(&pool_ratio) => {
+				return Err(SurgeError::InvalidConfig(format!("pool_ratio must be within [0, 1], got {}", pool_ratio)));
+			}
+			_ => {}
+		}
+		Ok(())
+	}
 }
 
-pub struct RolloutManager<'a>  {
-	instances: Vec<RolloutWorker<'a>>,
+pub struct RolloutManager {
+	conf: RolloutConfig,
+	model_store: Arc<ModelStore>,
+	num_workers: usize,
+	// rated with every finished match and saved once the run ends
+	ratings: Option<Arc<RatingStore>>,
+}
+
+impl RolloutManager {
+	pub fn new(conf: RolloutConfig, model_store: Arc<ModelStore>, num_workers: usize) -> Self {
+		RolloutManager {
+			conf,
+			model_store,
+			num_workers: num_workers.max(1),
+			ratings: None,
+		}
+	}
+
+	pub fn with_ratings(mut self, ratings: Arc<RatingStore>) -> Self {
+		self.ratings = Some(ratings);
+		self
+	}
+
+	pub fn config(self: &Self) -> &RolloutConfig {
+		&self.conf
+	}
 
-}
\ No newline at end of file
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		self.conf.validate()?;
+		// the workers match from the ratings in the store
+		if let Some(ratings) = self.ratings.as_ref() {
+			self.conf.matchmaking.validate_stats(ratings.system())?;
+		}
+		Ok(())
+	}
+
+	pub fn play_matches(self: &Self) -> Result<Vec<MatchReplay>, SurgeError> {
+		self.run_workers(None, Arc::new(AtomicBool::new(false)))
+	}
+
+	// sends every replay to sink as soon as its match ends, setting stop or dropping the receiver ends the run early
+	pub fn stream_matches(self: &Self, sink: Sender<MatchReplay>, stop: Arc<AtomicBool>) -> Result<(), SurgeError> {
+		self.run_workers(Some(sink), stop).map(|_| ())
+	}
+
+	fn run_workers(self: &Self, sink: Option<Sender<MatchReplay>>, stop: Arc<AtomicBool>) -> Result<Vec<MatchReplay>, SurgeError> {
+		self.validate()?;
+		let mut handles = vec![];
+		for (worker_idx, worker_conf) in self.worker_configs().into_iter().enumerate() {
+			let model_store = self.model_store.clone();
+			let worker_sink = sink.clone();
+			let worker_stop = stop.clone();
+			let worker_ratings = self.ratings.clone();
+			let handle = thread::Builder::new()
+				.name(format!("rollout-worker-{}", worker_idx))
+				.spawn(move || {
+					let mut worker = RolloutWorker::new(worker_conf, model_store);
+					if let Some(worker_sink) = worker_sink {
+						worker = worker.with_stream(worker_sink, worker_stop);
+					}
+					if let Some(worker_ratings) = worker_ratings {
+						worker = worker.with_ratings(worker_ratings);
+					}
+					worker.play_matches()
+				})
+				.map_err(|err| SurgeError::Worker(format!("failed to spawn rollout worker: {}", err)))?;
+			handles.push(handle);
+		}
+		// the stream ends once the last worker drops its sender
+		drop(sink);
+
+		let mut results = vec![];
+		for handle in handles {
+			results.push(handle.join()
+				.map_err(|_| SurgeError::Worker("rollout worker thread panicked".to_string())));
+		}
+		// matches played before a worker failed still count towards the ratings
+		if let Some(ratings) = self.ratings.as_ref() {
+			if let Err(err) = ratings.save() {
+				println!("Failed to save ratings: {}", err);
+			}
+		}
+
+		let mut match_history = vec![];
+		for result in results {
+			let mut replays = result??;
+			match_history.append(&mut replays);
+		}
+		Ok(match_history)
+	}
+
+	// split max_matches as evenly as possible, each worker gets its own engine seed
+	// one config per worker thread, max_matches split between them with the remainder going to the first ones
+	pub fn worker_configs(self: &Self) -> Vec<RolloutConfig> {
+		let num_workers = self.num_workers.min(self.conf.max_matches.max(1) as usize);
+		let base_matches = self.conf.max_matches / num_workers as u32;
+		let remainder = self.conf.max_matches % num_workers as u32;
+
+		(0..num_workers)
+			.map(|worker_idx| {
+				let mut conf = self.conf.clone();
+				conf.max_matches = base_matches + if (worker_idx as u32) < remainder { 1 } else { 0 };
+				conf.engine_config = EngineConfig {
+					random_seed: self.conf.engine_config.random_seed.wrapping_add(worker_idx as u64),
+					..self.conf.engine_config.clone()
+				};
+				conf
+			})
+			.filter(|conf| conf.max_matches > 0)
+			.collect()
+	}
+}
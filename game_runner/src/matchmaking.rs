@@ -0,0 +1,210 @@
+use rand::Rng;
+use rand::rngs::ThreadRng;
+use rand::seq::SliceRandom;
+
+use engine::state::MatchReplay;
+
+use crate::error::SurgeError;
+use crate::manager::MatchMode;
+use crate::ratings::{RatingSystem, RatingTable};
+
+// picks the target's next opponent from the pool, stats holds the results of every match seen so far
+pub trait MatchmakingStrategy {
+	fn sample(self: &mut Self, target_id: i32, pool: &[i32], stats: &RatingTable, rng: &mut ThreadRng) -> i32;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MatchmakingKind {
+	Uniform,
+	// always the newest opponent, agent ids are taken to grow with every version
+	Latest,
+	// prioritised fictitious self-play, opponents weighted by loss_rate ^ exponent
+	Pfsp { exponent: f32 },
+	// opponents rated within width of the target, the closest one when none are
+	EloBand { width: f64 },
+}
+
+impl MatchmakingKind {
+	pub const DEFAULT_PFSP_EXPONENT: f32 = 2.0;
+	pub const DEFAULT_ELO_BAND: f64 = 200.0;
+
+	pub fn name(self: &Self) -> &'static str {
+		match self {
+			MatchmakingKind::Uniform => "uniform",
+			MatchmakingKind::Latest => "latest",
+			MatchmakingKind::Pfsp { .. } => "pfsp",
+			MatchmakingKind::EloBand { .. } => "elo_band",
+		}
+	}
+
+	// strategies with settings get their defaults
+	pub fn from_name(name: &str) -> Option<MatchmakingKind> {
+		match name {
+			"uniform" => Some(MatchmakingKind::Uniform),
+			"latest" => Some(MatchmakingKind::Latest),
+			"pfsp" => Some(MatchmakingKind::Pfsp { exponent: MatchmakingKind::DEFAULT_PFSP_EXPONENT }),
+			"elo_band" => Some(MatchmakingKind::EloBand { width: MatchmakingKind::DEFAULT_ELO_BAND }),
+			_ => None,
+		}
+	}
+
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		match self {
+			MatchmakingKind::Pfsp { exponent } if !(*exponent >= 0.0) => {
+				Err(SurgeError::InvalidConfig(format!("pfsp exponent must not be negative, got {}", exponent)))
+			}
+			MatchmakingKind::EloBand { width } if !(*width > 0.0) => {
+				Err(SurgeError::InvalidConfig(format!("elo band width must be positive, got {}", width)))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	// elo_band widths are in Elo points, so it can't pick from TrueSkill ratings
+	pub fn validate_stats(self: &Self, system: RatingSystem) -> Result<(), SurgeError> {
+		match (self, system) {
+			(MatchmakingKind::EloBand { .. }, RatingSystem::TrueSkill) => {
+				Err(SurgeError::InvalidConfig("elo_band matchmaking needs Elo ratings, not trueskill".to_string()))
+			}
+			_ => Ok(()),
+		}
+	}
+
+	pub fn build(self: &Self) -> Box<dyn MatchmakingStrategy + Send> {
+		match *self {
+			MatchmakingKind::Uniform => Box::new(UniformStrategy),
+			MatchmakingKind::Latest => Box::new(LatestStrategy),
+			MatchmakingKind::Pfsp { exponent } => Box::new(PfspStrategy { exponent }),
+			MatchmakingKind::EloBand { width } => Box::new(EloBandStrategy { width }),
+		}
+	}
+}
+
+pub struct UniformStrategy;
+
+impl MatchmakingStrategy for UniformStrategy {
+	fn sample(self: &mut Self, _target_id: i32, pool: &[i32], _stats: &RatingTable, rng: &mut ThreadRng) -> i32 {
+		*pool.choose(rng).unwrap()
+	}
+}
+
+pub struct LatestStrategy;
+
+impl MatchmakingStrategy for LatestStrategy {
+	fn sample(self: &mut Self, _target_id: i32, pool: &[i32], _stats: &RatingTable, _rng: &mut ThreadRng) -> i32 {
+		*pool.iter().max().unwrap()
+	}
+}
+
+// Spends the matches on the opponents the target loses to, as in AlphaStar's league.
+// An opponent's loss rate counts draws as half a loss and starts from one win and one
+// loss, so opponents that haven't been played yet are tried at the rate of an even match.
+pub struct PfspStrategy {
+	pub exponent: f32,
+}
+
+impl PfspStrategy {
+	pub fn loss_rate(target_id: i32, opponent_id: i32, stats: &RatingTable) -> f32 {
+		let record = stats.head_to_head(target_id, opponent_id);
+		let played = record.wins + record.losses + record.draws;
+		(record.losses as f32 + 0.5f32 * record.draws as f32 + 1.0f32) / (played as f32 + 2.0f32)
+	}
+}
+
+impl MatchmakingStrategy for PfspStrategy {
+	fn sample(self: &mut Self, target_id: i32, pool: &[i32], stats: &RatingTable, rng: &mut ThreadRng) -> i32 {
+		let weights = pool.iter()
+			.map(|opponent_id| PfspStrategy::loss_rate(target_id, *opponent_id, stats).powf(self.exponent))
+			.collect::<Vec<f32>>();
+		let mut threshold = rng.gen::<f32>() * weights.iter().sum::<f32>();
+		pool.iter()
+			.zip(weights.iter())
+			.find(|(_, weight)| {
+				threshold -= **weight;
+				threshold < 0.0f32
+			})
+			.map(|(opponent_id, _)| *opponent_id)
+			.unwrap_or(*pool.last().unwrap())
+	}
+}
+
+pub struct EloBandStrategy {
+	pub width: f64,
+}
+
+impl MatchmakingStrategy for EloBandStrategy {
+	fn sample(self: &mut Self, target_id: i32, pool: &[i32], stats: &RatingTable, rng: &mut ThreadRng) -> i32 {
+		let target_rating = stats.rating(target_id).rating;
+		let gap = |opponent_id: &i32| (stats.rating(*opponent_id).rating - target_rating).abs();
+		let in_band = pool.iter()
+			.copied()
+			.filter(|opponent_id| gap(opponent_id) <= self.width)
+			.collect::<Vec<i32>>();
+		match in_band.choose(rng) {
+			Some(opponent_id) => *opponent_id,
+			None => *pool.iter().min_by(|a, b| gap(a).total_cmp(&gap(b))).unwrap(),
+		}
+	}
+}
+
+// opponents of one target agent, along with the results the strategy picks them by
+pub struct MatchmakingPool {
+	target_id: i32,
+	opponent_ids: Vec<i32>,
+	strategy: Box<dyn MatchmakingStrategy + Send>,
+	stats: RatingTable,
+	rng: ThreadRng,
+}
+
+impl MatchmakingPool {
+	pub fn new(target: i32, opponents: Vec<i32>) -> Self {
+		Self {
+			target_id: target,
+			opponent_ids: opponents,
+			strategy: Box::new(UniformStrategy),
+			stats: RatingTable::new(RatingSystem::DEFAULT_ELO),
+			rng: ThreadRng::default(),
+		}
+	}
+
+	pub fn with_strategy(mut self, strategy: Box<dyn MatchmakingStrategy + Send>) -> Self {
+		self.strategy = strategy;
+		self
+	}
+
+	pub fn target_id(self: &Self) -> i32 {
+		self.target_id
+	}
+
+	pub fn stats(self: &Self) -> &RatingTable {
+		&self.stats
+	}
+
+	// replaces the stats with results gathered elsewhere, e.g. by earlier rollouts
+	pub fn seed(self: &mut Self, stats: RatingTable) {
+		self.stats = stats;
+	}
+
+	pub fn record(self: &mut Self, replay: &MatchReplay) {
+		self.stats.record(replay.agent_ids, replay.outcome);
+	}
+
+	pub fn sample_pair(self: &mut Self) -> (i32, i32) {
+		let opponent = self.strategy.sample(self.target_id, &self.opponent_ids, &self.stats, &mut self.rng);
+		(self.target_id, opponent)
+	}
+
+	// None means the match is played against the scripted opponent
+	pub fn sample_opponent(self: &mut Self, mode: MatchMode) -> Option<i32> {
+		let vs_pool = match mode {
+			MatchMode::VsRandom => false,
+			MatchMode::VsPool => true,
+			MatchMode::Mixed(pool_ratio) => self.rng.gen::<f32>() < pool_ratio,
+		};
+		if vs_pool {
+			Some(self.sample_pair().1)
+		} else {
+			None
+		}
+	}
+}
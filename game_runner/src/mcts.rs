@@ -0,0 +1,333 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use engine::engine::{Engine, EngineConfig};
+use engine::observation::ObservationEncoder;
+use engine::state::{Action, Direction, GameState, PlayerIdx, WinState};
+
+use crate::ai::{AiPlayer, ScriptedOpponent};
+use crate::error::SurgeError;
+use crate::inference::Policy;
+use crate::worker::mask_logits;
+
+const MOVES: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MctsConfig {
+	// simulations run for every move
+	pub playouts: u32,
+	// weight of the exploration term, returns are scaled by the win reward before it is applied
+	pub exploration: f32,
+	// bot followed by both seats once a playout leaves the tree, not used when a model values the leaves
+	pub rollout_policy: ScriptedOpponent,
+	// steps a rollout plays past the tree before it is cut off
+	pub rollout_depth: u32,
+	// agent whose model gives the priors and leaf values
+	pub prior_model: Option<i32>,
+}
+
+impl Default for MctsConfig {
+	fn default() -> Self {
+		MctsConfig {
+			playouts: 200,
+			exploration: 1.0f32,
+			rollout_policy: ScriptedOpponent::Greedy,
+			rollout_depth: 20,
+			prior_model: None,
+		}
+	}
+}
+
+impl MctsConfig {
+	pub fn validate(self: &Self) -> Result<(), SurgeError> {
+		if self.playouts == 0 {
+			return Err(SurgeError::InvalidConfig("mcts playouts must be positive".to_string()));
+		}
+		if !(self.exploration >= 0.0) {
+			return Err(SurgeError::InvalidConfig(format!("mcts exploration must not be negative, got {}", self.exploration)));
+		}
+		if self.rollout_policy == ScriptedOpponent::Mcts {
+			return Err(SurgeError::InvalidConfig("mcts can't roll out with itself".to_string()));
+		}
+		Ok(())
+	}
+}
+
+// visit counts of the root after a search, usable as expert policy targets
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchResult {
+	// per seat, in Up, Down, Left, Right order
+	pub visits: ([u32; 4], [u32; 4]),
+	// mean return of each seat from the root
+	pub values: (f32, f32),
+}
+
+impl SearchResult {
+	pub fn policy(self: &Self, seat: PlayerIdx) -> Vec<f32> {
+		let visits = self.seat_visits(seat);
+		let total = visits.iter().sum::<u32>().max(1) as f32;
+		visits.iter().map(|count| *count as f32 / total).collect()
+	}
+
+	// most visited move, DoNothing when the search never left the root
+	pub fn best_action(self: &Self, seat: PlayerIdx) -> Action {
+		let visits = self.seat_visits(seat);
+		MOVES.iter()
+			.zip(visits.iter())
+			.filter(|(_, count)| **count > 0)
+			.fold(None, |best: Option<(Direction, u32)>, (direction, count)| match best {
+				Some((_, best_count)) if best_count >= *count => best,
+				_ => Some((*direction, *count)),
+			})
+			.map(|(direction, _)| Action::Move(direction))
+			.unwrap_or(Action::DoNothing)
+	}
+
+	fn seat_visits(self: &Self, seat: PlayerIdx) -> &[u32; 4] {
+		match seat {
+			PlayerIdx::Player1 => &self.visits.0,
+			PlayerIdx::Player2 => &self.visits.1,
+		}
+	}
+}
+
+struct Edge {
+	action: Action,
+	prior: f32,
+	visits: u32,
+	// summed returns of the playouts that took this move
+	total: f32,
+}
+
+struct Node {
+	state: GameState,
+	// rewards of the step into this node
+	reward: (f32, f32),
+	terminal: bool,
+	visits: u32,
+	// every seat picks from its own moves, children are keyed by the joint choice
+	edges: (Vec<Edge>, Vec<Edge>),
+	children: HashMap<(usize, usize), usize>,
+}
+
+// Simultaneous-move search with decoupled UCT: each seat keeps its own statistics over its
+// own moves and picks independently, the pair of picks selects the child. Without a model
+// both seats explore with UCB1 and leaves are valued by a rollout, with one the priors weigh
+// the exploration term (PUCT) and the model's value estimates replace the rollouts.
+// Returns are the undiscounted sum of the engine rewards.
+pub struct MctsPlayer {
+	conf: MctsConfig,
+	// stepped from the state of each node, its history is cleared before every step
+	engine: Engine,
+	max_rounds: u32,
+	rollout_player: Box<dyn AiPlayer>,
+	model: Option<(Arc<Policy>, Box<dyn ObservationEncoder + Send>)>,
+}
+
+impl MctsPlayer {
+	pub fn new(engine_config: EngineConfig, max_rounds: u32, conf: MctsConfig) -> Self {
+		let rollout_player = conf.rollout_policy.player()
+			.unwrap_or_else(|| ScriptedOpponent::Random.player().unwrap());
+		MctsPlayer {
+			conf,
+			engine: Engine::new(engine_config),
+			max_rounds,
+			rollout_player,
+			model: None,
+		}
+	}
+
+	pub fn with_model(self, model: Arc<Policy>, encoder: Box<dyn ObservationEncoder + Send>) -> Self {
+		MctsPlayer {
+			model: Some((model, encoder)),
+			..self
+		}
+	}
+
+	// runs the model on state once, so one that can't read the observations fails up front instead of on every move
+	pub fn check_model(self: &Self, state: &GameState) -> Result<(), SurgeError> {
+		if let Some((model, encoder)) = self.model.as_ref() {
+			let states = [encoder.encode(state, PlayerIdx::Player1), encoder.encode(state, PlayerIdx::Player2)];
+			model.evaluate(&states)
+				.map_err(|err| SurgeError::Inference(self.conf.prior_model.unwrap_or_default(), err))?;
+		}
+		Ok(())
+	}
+
+	pub fn search(self: &mut Self, state: &GameState) -> Result<SearchResult, SurgeError> {
+		let mut nodes = vec![self.expand(state.clone(), (0.0f32, 0.0f32))?.0];
+		for _ in 0..self.conf.playouts {
+			if nodes[0].terminal {
+				break;
+			}
+			self.playout(&mut nodes)?;
+		}
+
+		let root = &nodes[0];
+		let seat_visits = |edges: &Vec<Edge>| {
+			let mut visits = [0u32; 4];
+			for edge in edges {
+				if let Action::Move(direction) = edge.action {
+					visits[direction as usize] = edge.visits;
+				}
+			}
+			visits
+		};
+		let mean_return = |edges: &Vec<Edge>| edges.iter().map(|edge| edge.total).sum::<f32>() / root.visits.max(1) as f32;
+		Ok(SearchResult {
+			visits: (seat_visits(&root.edges.0), seat_visits(&root.edges.1)),
+			values: (mean_return(&root.edges.0), mean_return(&root.edges.1)),
+		})
+	}
+
+	fn playout(self: &mut Self, nodes: &mut Vec<Node>) -> Result<(), SurgeError> {
+		let mut path = vec![];
+		let mut node_idx = 0;
+		let mut leaf_value = (0.0f32, 0.0f32);
+		while !nodes[node_idx].terminal {
+			let joint = (self.select(&nodes[node_idx], &nodes[node_idx].edges.0),
+						 self.select(&nodes[node_idx], &nodes[node_idx].edges.1));
+			path.push((node_idx, joint));
+			if let Some(child_idx) = nodes[node_idx].children.get(&joint) {
+				node_idx = *child_idx;
+				continue;
+			}
+			let actions = (nodes[node_idx].edges.0[joint.0].action, nodes[node_idx].edges.1[joint.1].action);
+			let (state, reward) = self.step(&nodes[node_idx].state, actions);
+			let (child, value) = self.expand(state, reward)?;
+			nodes.push(child);
+			let child_idx = nodes.len() - 1;
+			nodes[node_idx].children.insert(joint, child_idx);
+			node_idx = child_idx;
+			leaf_value = value;
+			break;
+		}
+
+		// each node is credited with the rewards collected after it plus the leaf value
+		let mut value = leaf_value;
+		let mut child_idx = node_idx;
+		for (node_idx, (move1, move2)) in path.into_iter().rev() {
+			value = (value.0 + nodes[child_idx].reward.0, value.1 + nodes[child_idx].reward.1);
+			let node = &mut nodes[node_idx];
+			node.visits += 1;
+			node.edges.0[move1].visits += 1;
+			node.edges.0[move1].total += value.0;
+			node.edges.1[move2].visits += 1;
+			node.edges.1[move2].total += value.1;
+			child_idx = node_idx;
+		}
+		Ok(())
+	}
+
+	fn select(self: &Self, node: &Node, edges: &[Edge]) -> usize {
+		let scale = self.engine.config.win_reward.abs().max(1.0f32);
+		let parent_visits = node.visits.max(1) as f32;
+		let score = |edge: &Edge| {
+			let mean = if edge.visits > 0 { edge.total / edge.visits as f32 / scale } else { 0.0f32 };
+			if self.model.is_some() {
+				mean + self.conf.exploration * edge.prior * parent_visits.sqrt() / (1 + edge.visits) as f32
+			} else if edge.visits == 0 {
+				f32::INFINITY
+			} else {
+				mean + self.conf.exploration * (parent_visits.ln() / edge.visits as f32).sqrt()
+			}
+		};
+		edges.iter()
+			.map(score)
+			.enumerate()
+			.fold((0, f32::NEG_INFINITY), |best, (idx, value)| if value > best.1 { (idx, value) } else { best })
+			.0
+	}
+
+	fn step(self: &mut Self, state: &GameState, actions: (Action, Action)) -> (GameState, (f32, f32)) {
+		self.engine.current_state = state.clone();
+		self.engine.game_history.clear();
+		self.engine.apply_move(actions, None);
+		let reward = self.engine.game_history.last().map(|sar| sar.rewards).unwrap_or((0.0f32, 0.0f32));
+		(self.engine.current_state.clone(), reward)
+	}
+
+	// builds the node for a state along with the value of the state for each seat
+	fn expand(self: &mut Self, state: GameState, reward: (f32, f32)) -> Result<(Node, (f32, f32)), SurgeError> {
+		let mut node = Node {
+			terminal: state.match_status != WinState::InProgress || state.round >= self.max_rounds,
+			state,
+			reward,
+			visits: 0,
+			edges: (vec![], vec![]),
+			children: HashMap::new(),
+		};
+		if node.state.match_status != WinState::InProgress {
+			return Ok((node, (0.0f32, 0.0f32)));
+		}
+
+		let (priors, value) = match self.model.as_ref() {
+			Some((model, encoder)) => {
+				let seats = [PlayerIdx::Player1, PlayerIdx::Player2];
+				let states = seats.iter().map(|seat| encoder.encode(&node.state, *seat)).collect::<Vec<Vec<f32>>>();
+				let outputs = model.evaluate(&states)
+					.map_err(|err| SurgeError::Inference(self.conf.prior_model.unwrap_or_default(), err))?;
+				let priors = seats.iter()
+					.zip(outputs.iter())
+					.map(|(seat, (logits, _))| {
+						let action_mask = GameState::get_action_mask_vec(&node.state, *seat);
+						softmax(&mask_logits(logits[..MOVES.len()].to_vec(), &action_mask))
+					})
+					.collect::<Vec<Vec<f32>>>();
+				((priors[0].clone(), priors[1].clone()), (outputs[0].1, outputs[1].1))
+			}
+			None => {
+				let uniform = vec![1.0f32 / MOVES.len() as f32; MOVES.len()];
+				((uniform.clone(), uniform), self.rollout(&node.state)?)
+			}
+		};
+		node.edges = (
+			MctsPlayer::edges(&node.state, PlayerIdx::Player1, &priors.0),
+			MctsPlayer::edges(&node.state, PlayerIdx::Player2, &priors.1),
+		);
+		Ok((node, value))
+	}
+
+	// a seat with no legal move waits in place
+	fn edges(state: &GameState, seat: PlayerIdx, priors: &[f32]) -> Vec<Edge> {
+		let action_mask = GameState::get_action_mask_vec(state, seat);
+		let mut edges = MOVES.iter()
+			.enumerate()
+			.filter(|(idx, _)| action_mask[*idx] == 0.0f32)
+			.map(|(idx, direction)| Edge { action: Action::Move(*direction), prior: priors[idx], visits: 0, total: 0.0f32 })
+			.collect::<Vec<Edge>>();
+		if edges.is_empty() {
+			edges.push(Edge { action: Action::DoNothing, prior: 1.0f32, visits: 0, total: 0.0f32 });
+		}
+		edges
+	}
+
+	fn rollout(self: &mut Self, state: &GameState) -> Result<(f32, f32), SurgeError> {
+		let mut state = state.clone();
+		let mut value = (0.0f32, 0.0f32);
+		for _ in 0..self.conf.rollout_depth {
+			if state.match_status != WinState::InProgress || state.round >= self.max_rounds {
+				break;
+			}
+			let actions = (self.rollout_player.get_move(&state, PlayerIdx::Player1)?,
+						   self.rollout_player.get_move(&state, PlayerIdx::Player2)?);
+			let (next_state, reward) = self.step(&state, actions);
+			value = (value.0 + reward.0, value.1 + reward.1);
+			state = next_state;
+		}
+		Ok(value)
+	}
+}
+
+impl AiPlayer for MctsPlayer {
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		Ok(self.search(current_state)?.best_action(seat))
+	}
+}
+
+fn softmax(logits: &[f32]) -> Vec<f32> {
+	let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+	let exps = logits.iter().map(|logit| (logit - max).exp()).collect::<Vec<f32>>();
+	let sum = exps.iter().sum::<f32>();
+	exps.iter().map(|exp| exp / sum).collect()
+}
@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fs;
+use std::path::Path;
+
+use ndarray::{Array1, Array2};
+use serde_json::Value;
+
+use crate::inference::{InferenceBackend, ModelOutputs};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activation {
+	Relu,
+	Tanh,
+}
+
+struct DenseLayer {
+	// [out, in] like torch.nn.Linear
+	weight: Array2<f32>,
+	bias: Array1<f32>,
+}
+
+// Feed-forward policy run with ndarray, no libtorch needed.
+// Weights come from a safetensors file holding an F32 `{prefix}.weight` and `{prefix}.bias`
+// per linear layer, as written by safetensors.torch.save_file(model.state_dict()). Layers run
+// in the numeric order of their prefixes and `"activation": "relu" | "tanh"` in the
+// metadata picks the activation between them, relu by default.
+pub struct MlpBackend {
+	layers: Vec<DenseLayer>,
+	activation: Activation,
+}
+
+impl MlpBackend {
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let bytes = fs::read(path).map_err(|err| err.to_string())?;
+		MlpBackend::from_safetensors(&bytes)
+	}
+
+	pub fn from_safetensors(bytes: &[u8]) -> Result<Self, String> {
+		let (tensors, metadata) = read_safetensors(bytes)?;
+		let activation = match metadata.get("activation").map(|activation| activation.as_str()) {
+			None | Some("relu") => Activation::Relu,
+			Some("tanh") => Activation::Tanh,
+			Some(other) => return Err(format!("unsupported activation {}", other)),
+		};
+
+		let mut prefixes = tensors.keys()
+			.filter_map(|name| name.strip_suffix(".weight"))
+			.map(|prefix| prefix.to_string())
+			.collect::<Vec<String>>();
+		prefixes.sort_by_key(|prefix| layer_order(prefix));
+		if prefixes.is_empty() {
+			return Err("no linear layers found".to_string());
+		}
+
+		let mut layers: Vec<DenseLayer> = vec![];
+		for prefix in prefixes {
+			let (weight_shape, weight) = &tensors[&format!("{}.weight", prefix)];
+			let (bias_shape, bias) = tensors.get(&format!("{}.bias", prefix))
+				.ok_or_else(|| format!("layer {} has no bias", prefix))?;
+			if weight_shape.len() != 2 || bias_shape.len() != 1 || bias_shape[0] != weight_shape[0] {
+				return Err(format!("layer {} has weight {:?} and bias {:?}", prefix, weight_shape, bias_shape));
+			}
+			if let Some(previous) = layers.last() {
+				if previous.weight.nrows() != weight_shape[1] {
+					return Err(format!("layer {} takes {} inputs but the previous layer has {} outputs",
+									   prefix, weight_shape[1], previous.weight.nrows()));
+				}
+			}
+			layers.push(DenseLayer {
+				weight: Array2::from_shape_vec((weight_shape[0], weight_shape[1]), weight.clone())
+					.map_err(|err| err.to_string())?,
+				bias: Array1::from(bias.clone()),
+			});
+		}
+
+		Ok(MlpBackend {
+			layers,
+			activation,
+		})
+	}
+
+	pub fn input_size(self: &Self) -> usize {
+		self.layers[0].weight.ncols()
+	}
+}
+
+impl InferenceBackend for MlpBackend {
+	fn forward(self: &Self, states: &[Vec<f32>]) -> Result<ModelOutputs, String> {
+		if states.is_empty() {
+			return Ok(vec![]);
+		}
+		if let Some(state) = states.iter().find(|state| state.len() != self.input_size()) {
+			return Err(format!("model expects {} inputs, got {}", self.input_size(), state.len()));
+		}
+		let mut hidden = Array2::from_shape_vec((states.len(), self.input_size()), states.concat())
+			.map_err(|err| err.to_string())?;
+		for (layer_idx, layer) in self.layers.iter().enumerate() {
+			hidden = hidden.dot(&layer.weight.t()) + &layer.bias;
+			if layer_idx + 1 < self.layers.len() {
+				match self.activation {
+					Activation::Relu => hidden.mapv_inplace(|x| x.max(0.0f32)),
+					Activation::Tanh => hidden.mapv_inplace(f32::tanh),
+				}
+			}
+		}
+		Ok(vec![hidden.outer_iter().map(|row| row.to_vec()).collect()])
+	}
+}
+
+// "policy.10" sorts after "policy.2"
+fn layer_order(prefix: &str) -> Vec<(u64, String)> {
+	prefix.split('.')
+		.map(|part| match part.parse::<u64>() {
+			Ok(idx) => (idx, String::new()),
+			Err(_) => (0, part.to_string()),
+		})
+		.collect()
+}
+
+type Tensors = HashMap<String, (Vec<usize>, Vec<f32>)>;
+
+// an 8 byte little endian header length, a JSON header of
+// {name: {"dtype", "shape", "data_offsets": [begin, end]}} and the tensor data after it
+fn read_safetensors(bytes: &[u8]) -> Result<(Tensors, HashMap<String, String>), String> {
+	if bytes.len() < 8 {
+		return Err("file is too short for a safetensors header".to_string());
+	}
+	let header_len = u64::from_le_bytes(bytes[..8].try_into().unwrap()) as usize;
+	let data_start = 8usize.checked_add(header_len)
+		.filter(|data_start| *data_start <= bytes.len())
+		.ok_or_else(|| "safetensors header is longer than the file".to_string())?;
+	let header: Value = serde_json::from_slice(&bytes[8..data_start]).map_err(|err| err.to_string())?;
+	let header = header.as_object().ok_or_else(|| "safetensors header is not an object".to_string())?;
+	let data = &bytes[data_start..];
+
+	let mut tensors = HashMap::new();
+	let mut metadata = HashMap::new();
+	for (name, info) in header {
+		if name == "__metadata__" {
+			if let Some(entries) = info.as_object() {
+				for (key, value) in entries {
+					metadata.insert(key.clone(), value.as_str().unwrap_or_default().to_string());
+				}
+			}
+			continue;
+		}
+		let dtype = info["dtype"].as_str().unwrap_or_default();
+		if dtype != "F32" {
+			return Err(format!("tensor {} is {}, only F32 is supported", name, dtype));
+		}
+		let shape = info["shape"].as_array()
+			.map(|dims| dims.iter().filter_map(|dim| dim.as_u64()).map(|dim| dim as usize).collect::<Vec<usize>>())
+			.ok_or_else(|| format!("tensor {} has no shape", name))?;
+		let offsets = info["data_offsets"].as_array()
+			.map(|offsets| offsets.iter().filter_map(|offset| offset.as_u64()).map(|offset| offset as usize).collect::<Vec<usize>>())
+			.filter(|offsets| offsets.len() == 2 && offsets[0] <= offsets[1] && offsets[1] <= data.len())
+			.ok_or_else(|| format!("tensor {} has invalid data offsets", name))?;
+		let raw = &data[offsets[0]..offsets[1]];
+		if raw.len() != shape.iter().product::<usize>() * 4 {
+			return Err(format!("tensor {} holds {} bytes for shape {:?}", name, raw.len(), shape));
+		}
+		let values = raw.chunks_exact(4)
+			.map(|value| f32::from_le_bytes(value.try_into().unwrap()))
+			.collect();
+		tensors.insert(name.clone(), (shape, values));
+	}
+	Ok((tensors, metadata))
+}
@@ -0,0 +1,237 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::error::SurgeError;
+use crate::inference::{self, ModelFormat, OutputSpec, Policy};
+
+// Server-wide cache of models stored as `{id}.pt` (TorchScript), `{id}.safetensors` or `{id}.onnx` under root_path,
+// each with an optional `{id}.spec.json` OutputSpec, the default spec is used without one.
+// Models are loaded on first use, evicted least recently used first once either
+// bound is exceeded, and reloaded when the file on disk changes.
+pub struct ModelStore {
+	root_path: PathBuf,
+	max_models: usize,
+	// approximated by the size of the model files, 0 means unbounded
+	max_bytes: u64,
+	cache: Mutex<ModelCache>,
+}
+
+#[derive(Default)]
+struct ModelCache {
+	entries: HashMap<i32, CachedModel>,
+	clock: u64,
+}
+
+struct CachedModel {
+	model: Arc<Policy>,
+	modified: SystemTime,
+	size_bytes: u64,
+	last_used: u64,
+}
+
+impl ModelStore {
+	pub fn new<P: AsRef<Path>>(path: P, max_models: usize, max_bytes: u64) -> Self {
+		ModelStore {
+			root_path: path.as_ref().to_path_buf(),
+			max_models: max_models.max(1),
+			max_bytes,
+			cache: Mutex::new(ModelCache::default()),
+		}
+	}
+
+	pub fn root_path(self: &Self) -> &Path {
+		&self.root_path
+	}
+
+	pub fn model_path(root_path: &Path, id: i32, format: ModelFormat) -> PathBuf {
+		root_path.join(format!("{}.{}", id, format.extension()))
+	}
+
+	pub fn spec_path(root_path: &Path, id: i32) -> PathBuf {
+		root_path.join(format!("{}.spec.json", id))
+	}
+
+	pub fn output_spec(self: &Self, id: i32) -> Result<OutputSpec, SurgeError> {
+		let spec_path = ModelStore::spec_path(&self.root_path, id);
+		if !spec_path.exists() {
+			return Ok(OutputSpec::default());
+		}
+		fs::read(&spec_path)
+			.map_err(|err| err.to_string())
+			.and_then(|bytes| OutputSpec::from_json(&bytes))
+			.map_err(|err| SurgeError::ModelLoad(id, format!("invalid output spec: {}", err)))
+	}
+
+	// an agent has at most one model file, whichever format it was uploaded in
+	fn find_model(self: &Self, id: i32) -> Option<(PathBuf, ModelFormat)> {
+		ModelFormat::ALL.iter()
+			.map(|format| (ModelStore::model_path(&self.root_path, id, *format), *format))
+			.find(|(model_path, _)| model_path.exists())
+	}
+
+	pub fn get(self: &Self, id: i32) -> Result<Arc<Policy>, SurgeError> {
+		let (model_path, format) = self.find_model(id).ok_or(SurgeError::ModelNotFound(id))?;
+		let metadata = fs::metadata(&model_path).map_err(|_| SurgeError::ModelNotFound(id))?;
+		let modified = metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+
+		{
+			let mut cache = self.cache.lock().unwrap();
+			cache.clock += 1;
+			let clock = cache.clock;
+			if let Some(entry) = cache.entries.get_mut(&id) {
+				if entry.modified == modified {
+					entry.last_used = clock;
+					return Ok(entry.model.clone());
+				}
+			}
+		}
+
+		// load outside the lock so other workers keep running while the model deserialises
+		let backend = inference::load_backend(format, &model_path)
+			.map_err(|err| SurgeError::ModelLoad(id, err))?;
+		let model = Arc::new(Policy::new(backend, self.output_spec(id)?));
+		let mut cache = self.cache.lock().unwrap();
+		cache.clock += 1;
+		let clock = cache.clock;
+		cache.entries.insert(id, CachedModel {
+			model: model.clone(),
+			modified,
+			size_bytes: metadata.len(),
+			last_used: clock,
+		});
+		self.evict(&mut cache, id);
+		Ok(model)
+	}
+
+	pub fn cached_ids(self: &Self) -> Vec<i32> {
+		let cache = self.cache.lock().unwrap();
+		let mut ids = cache.entries.keys().copied().collect::<Vec<i32>>();
+		ids.sort();
+		ids
+	}
+
+	fn evict(self: &Self, cache: &mut ModelCache, keep_id: i32) {
+		loop {
+			let total_bytes: u64 = cache.entries.values().map(|entry| entry.size_bytes).sum();
+			let over_count = cache.entries.len() > self.max_models;
+			let over_bytes = self.max_bytes > 0 && total_bytes > self.max_bytes;
+			if !over_count && !over_bytes {
+				break;
+			}
+			let lru_id = cache.entries.iter()
+				.filter(|(id, _)| **id != keep_id)
+				.min_by_key(|(_, entry)| entry.last_used)
+				.map(|(id, _)| *id);
+			match lru_id {
+				Some(id) => {
+					cache.entries.remove(&id);
+				}
+				None => break,
+			}
+		}
+	}
+
+	fn invalidate(self: &Self, id: i32) {
+		self.cache.lock().unwrap().entries.remove(&id);
+	}
+
+	// writes model bytes for an agent, rejecting anything its backend can't load
+	pub fn save_model(self: &Self, id: i32, format: ModelFormat, output_spec: OutputSpec, model_bytes: &[u8]) -> io::Result<PathBuf> {
+		output_spec.validate().map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+		fs::create_dir_all(&self.root_path)?;
+		let model_path = ModelStore::model_path(&self.root_path, id, format);
+		let tmp_path = model_path.with_extension(format!("{}.tmp", format.extension()));
+		fs::write(&tmp_path, model_bytes)?;
+
+		if let Err(err) = inference::load_backend(format, &tmp_path) {
+			fs::remove_file(&tmp_path)?;
+			return Err(io::Error::new(io::ErrorKind::InvalidData,
+									  format!("model {} is not a valid {:?} model: {}", id, format, err)));
+		}
+		// drop the agent's model in any other format so the new upload is the one served
+		for other in ModelFormat::ALL.iter().filter(|other| **other != format) {
+			let other_path = ModelStore::model_path(&self.root_path, id, *other);
+			if other_path.exists() {
+				fs::remove_file(other_path)?;
+			}
+		}
+		let spec_path = ModelStore::spec_path(&self.root_path, id);
+		if output_spec == OutputSpec::default() {
+			if spec_path.exists() {
+				fs::remove_file(&spec_path)?;
+			}
+		} else {
+			fs::write(&spec_path, output_spec.to_json())?;
+		}
+		fs::rename(&tmp_path, &model_path)?;
+		self.invalidate(id);
+		Ok(model_path)
+	}
+
+	// stores id's current model and output spec under to_id as well, replacing whatever to_id had
+	pub fn copy_model(self: &Self, id: i32, to_id: i32) -> io::Result<PathBuf> {
+		let (model_path, format) = self.find_model(id)
+			.ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, SurgeError::ModelNotFound(id).to_string()))?;
+		let to_path = ModelStore::model_path(&self.root_path, to_id, format);
+		let tmp_path = to_path.with_extension(format!("{}.tmp", format.extension()));
+		fs::copy(&model_path, &tmp_path)?;
+		for other in ModelFormat::ALL.iter().filter(|other| **other != format) {
+			let other_path = ModelStore::model_path(&self.root_path, to_id, *other);
+			if other_path.exists() {
+				fs::remove_file(other_path)?;
+			}
+		}
+		let spec_path = ModelStore::spec_path(&self.root_path, id);
+		let to_spec_path = ModelStore::spec_path(&self.root_path, to_id);
+		if spec_path.exists() {
+			fs::copy(&spec_path, &to_spec_path)?;
+		} else if to_spec_path.exists() {
+			fs::remove_file(&to_spec_path)?;
+		}
+		fs::rename(&tmp_path, &to_path)?;
+		self.invalidate(to_id);
+		Ok(to_path)
+	}
+
+	// (agent id, format, size in bytes) of every model in the store
+	pub fn list_models(self: &Self) -> io::Result<Vec<(i32, ModelFormat, u64)>> {
+		let mut models = vec![];
+		if !self.root_path.exists() {
+			return Ok(models);
+		}
+		for entry in fs::read_dir(&self.root_path)? {
+			let path = entry?.path();
+			let format = match path.extension().and_then(|ext| ext.to_str()).and_then(ModelFormat::from_extension) {
+				Some(format) => format,
+				None => continue,
+			};
+			let id = path.file_stem()
+				.and_then(|stem| stem.to_str())
+				.and_then(|stem| stem.parse::<i32>().ok());
+			if let Some(id) = id {
+				models.push((id, format, fs::metadata(&path)?.len()));
+			}
+		}
+		models.sort_by_key(|(id, _, _)| *id);
+		Ok(models)
+	}
+
+	pub fn delete_model(self: &Self, id: i32) -> io::Result<bool> {
+		self.invalidate(id);
+		let spec_path = ModelStore::spec_path(&self.root_path, id);
+		if spec_path.exists() {
+			fs::remove_file(spec_path)?;
+		}
+		match self.find_model(id) {
+			Some((model_path, _)) => {
+				fs::remove_file(model_path)?;
+				Ok(true)
+			}
+			None => Ok(false),
+		}
+	}
+}
@@ -0,0 +1,52 @@
+use std::path::Path;
+
+use tract_onnx::prelude::*;
+
+use crate::inference::{InferenceBackend, ModelOutputs};
+
+type OnnxPlan = SimplePlan<TypedFact, Box<dyn TypedOp>, Graph<TypedFact, Box<dyn TypedOp>>>;
+
+// ONNX policy run by tract. The model takes a [batch, features] f32 input and each of its
+// outputs is flattened to [batch, -1]. The plan is built from the input shape the model
+// declares, so its feature width must be fixed while the batch dimension can be symbolic.
+pub struct OnnxBackend {
+	plan: OnnxPlan,
+}
+
+impl OnnxBackend {
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let plan = tract_onnx::onnx()
+			.model_for_path(path)
+			.and_then(|model| model.into_optimized())
+			.and_then(|model| model.into_runnable())
+			.map_err(|err| err.to_string())?;
+		Ok(OnnxBackend {
+			plan
+		})
+	}
+}
+
+impl InferenceBackend for OnnxBackend {
+	fn forward(self: &Self, states: &[Vec<f32>]) -> Result<ModelOutputs, String> {
+		if states.is_empty() {
+			return Ok(vec![]);
+		}
+		let batch_size = states.len();
+		let input = tract_ndarray::Array2::from_shape_vec((batch_size, states[0].len()), states.concat())
+			.map_err(|err| err.to_string())?;
+		let outputs = self.plan.run(tvec!(Tensor::from(input).into()))
+			.map_err(|err| err.to_string())?;
+
+		outputs.iter()
+			.map(|output| {
+				let output = output.to_array_view::<f32>().map_err(|err| err.to_string())?;
+				if output.ndim() == 0 || output.shape()[0] != batch_size {
+					return Err(format!("expected outputs with a leading batch dimension of {}, got {:?}", batch_size, output.shape()));
+				}
+				let row_len = (output.len() / batch_size).max(1);
+				let values = output.iter().copied().collect::<Vec<f32>>();
+				Ok(values.chunks(row_len).map(|row| row.to_vec()).collect())
+			})
+			.collect()
+	}
+}
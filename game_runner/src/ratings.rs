@@ -0,0 +1,361 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use serde_json::{json, Value};
+
+use engine::state::{MatchReplay, PlayerWinner, WinState};
+
+use crate::json_store;
+
+// TrueSkill defaults from the original paper, in the usual 0..50 scale
+const TRUESKILL_MU: f64 = 25.0;
+const TRUESKILL_SIGMA: f64 = TRUESKILL_MU / 3.0;
+const TRUESKILL_BETA: f64 = TRUESKILL_SIGMA / 2.0;
+const TRUESKILL_TAU: f64 = TRUESKILL_SIGMA / 100.0;
+const TRUESKILL_DRAW_PROBABILITY: f64 = 0.1;
+const ELO_INITIAL: f64 = 1500.0;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RatingSystem {
+	Elo { k_factor: f64 },
+	// two player TrueSkill with draws
+	TrueSkill,
+}
+
+impl RatingSystem {
+	pub const DEFAULT_ELO: RatingSystem = RatingSystem::Elo { k_factor: 32.0 };
+
+	pub fn name(self: &Self) -> &'static str {
+		match self {
+			RatingSystem::Elo { .. } => "elo",
+			RatingSystem::TrueSkill => "trueskill",
+		}
+	}
+
+	pub fn from_name(name: &str) -> Option<RatingSystem> {
+		match name {
+			"elo" => Some(RatingSystem::DEFAULT_ELO),
+			"trueskill" => Some(RatingSystem::TrueSkill),
+			_ => None,
+		}
+	}
+
+	fn initial(self: &Self) -> Rating {
+		let (rating, deviation) = match self {
+			RatingSystem::Elo { .. } => (ELO_INITIAL, 0.0),
+			RatingSystem::TrueSkill => (TRUESKILL_MU, TRUESKILL_SIGMA),
+		};
+		Rating {
+			rating,
+			deviation,
+			matches: 0,
+			wins: 0,
+			losses: 0,
+			draws: 0,
+		}
+	}
+
+	// chance that a beats b, draws counting half for Elo
+	pub fn expected_score(self: &Self, a: &Rating, b: &Rating) -> f64 {
+		match self {
+			RatingSystem::Elo { .. } => 1.0 / (1.0 + 10f64.powf((b.rating - a.rating) / 400.0)),
+			RatingSystem::TrueSkill => {
+				let c = (2.0 * TRUESKILL_BETA.powi(2) + a.deviation.powi(2) + b.deviation.powi(2)).sqrt();
+				normal_cdf((a.rating - b.rating) / c)
+			}
+		}
+	}
+
+	// new ratings of a and b after a game where a scored score (1 win, 0.5 draw, 0 loss)
+	fn update(self: &Self, a: &Rating, b: &Rating, score: f64) -> (f64, f64, f64, f64) {
+		match self {
+			RatingSystem::Elo { k_factor } => {
+				let change = k_factor * (score - self.expected_score(a, b));
+				(a.rating + change, 0.0, b.rating - change, 0.0)
+			}
+			RatingSystem::TrueSkill => {
+				let var_a = a.deviation.powi(2) + TRUESKILL_TAU.powi(2);
+				let var_b = b.deviation.powi(2) + TRUESKILL_TAU.powi(2);
+				let c_sq = 2.0 * TRUESKILL_BETA.powi(2) + var_a + var_b;
+				let c = c_sq.sqrt();
+				let draw_margin = inverse_normal_cdf((TRUESKILL_DRAW_PROBABILITY + 1.0) / 2.0) * 2f64.sqrt() * TRUESKILL_BETA / c;
+				// (v, w) of the truncated gaussian, from the winner's side when there is one
+				let (sign, t) = if score < 0.5 { (-1.0, (b.rating - a.rating) / c) } else { (1.0, (a.rating - b.rating) / c) };
+				let (v, w) = if score == 0.5 {
+					let denominator = (normal_cdf(draw_margin - t) - normal_cdf(-draw_margin - t)).max(f64::MIN_POSITIVE);
+					let v = (normal_pdf(-draw_margin - t) - normal_pdf(draw_margin - t)) / denominator;
+					let w = v * v + ((draw_margin - t) * normal_pdf(draw_margin - t) + (draw_margin + t) * normal_pdf(draw_margin + t)) / denominator;
+					(v, w)
+				} else {
+					let v = normal_pdf(t - draw_margin) / normal_cdf(t - draw_margin).max(f64::MIN_POSITIVE);
+					(v, v * (v + t - draw_margin))
+				};
+				(
+					a.rating + sign * var_a / c * v,
+					(var_a * (1.0 - var_a / c_sq * w).max(f64::EPSILON)).sqrt(),
+					b.rating - sign * var_b / c * v,
+					(var_b * (1.0 - var_b / c_sq * w).max(f64::EPSILON)).sqrt(),
+				)
+			}
+		}
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rating {
+	// Elo rating or TrueSkill mean
+	pub rating: f64,
+	// TrueSkill standard deviation, 0 for Elo
+	pub deviation: f64,
+	pub matches: u32,
+	pub wins: u32,
+	pub losses: u32,
+	pub draws: u32,
+}
+
+// results of agent_id against opponent_id, from agent_id's side
+#[derive(Debug, Clone, PartialEq)]
+pub struct HeadToHead {
+	pub agent_id: i32,
+	pub opponent_id: i32,
+	pub wins: u32,
+	pub losses: u32,
+	pub draws: u32,
+	// chance agent_id beats opponent_id under the current ratings
+	pub expected_score: f64,
+}
+
+// ratings of every agent seen in a finished match. Timeouts count as draws and matches of an agent
+// against itself are ignored.
+#[derive(Clone)]
+pub struct RatingTable {
+	system: RatingSystem,
+	ratings: HashMap<i32, Rating>,
+	// (wins, losses, draws) of the lower id against the higher one
+	records: HashMap<(i32, i32), (u32, u32, u32)>,
+}
+
+impl RatingTable {
+	pub fn new(system: RatingSystem) -> Self {
+		RatingTable {
+			system,
+			ratings: HashMap::new(),
+			records: HashMap::new(),
+		}
+	}
+
+	pub fn system(self: &Self) -> RatingSystem {
+		self.system
+	}
+
+	pub fn record(self: &mut Self, agent_ids: (i32, i32), outcome: WinState) {
+		let score = match outcome {
+			WinState::InProgress => return,
+			WinState::Finished(PlayerWinner::Player1) => 1.0,
+			WinState::Finished(PlayerWinner::Player2) => 0.0,
+			WinState::Draw | WinState::Timeout => 0.5,
+		};
+		let (id1, id2) = agent_ids;
+		if id1 == id2 {
+			return;
+		}
+		let rating1 = self.rating(id1);
+		let rating2 = self.rating(id2);
+		let (new_rating1, deviation1, new_rating2, deviation2) = self.system.update(&rating1, &rating2, score);
+		self.ratings.insert(id1, RatingTable::updated(rating1, new_rating1, deviation1, score));
+		self.ratings.insert(id2, RatingTable::updated(rating2, new_rating2, deviation2, 1.0 - score));
+
+		let (key, lower_score) = if id1 < id2 { ((id1, id2), score) } else { ((id2, id1), 1.0 - score) };
+		let record = self.records.entry(key).or_insert((0, 0, 0));
+		if lower_score == 1.0 {
+			record.0 += 1;
+		} else if lower_score == 0.0 {
+			record.1 += 1;
+		} else {
+			record.2 += 1;
+		}
+	}
+
+	fn updated(mut rating: Rating, new_rating: f64, deviation: f64, score: f64) -> Rating {
+		rating.rating = new_rating;
+		rating.deviation = deviation;
+		rating.matches += 1;
+		if score == 1.0 {
+			rating.wins += 1;
+		} else if score == 0.0 {
+			rating.losses += 1;
+		} else {
+			rating.draws += 1;
+		}
+		rating
+	}
+
+	// an agent that hasn't played yet has the initial rating
+	pub fn rating(self: &Self, agent_id: i32) -> Rating {
+		self.ratings.get(&agent_id).cloned().unwrap_or_else(|| self.system.initial())
+	}
+
+	// every rated agent, best first
+	pub fn ratings(self: &Self) -> Vec<(i32, Rating)> {
+		let mut ratings = self.ratings.iter()
+			.map(|(id, rating)| (*id, rating.clone()))
+			.collect::<Vec<(i32, Rating)>>();
+		ratings.sort_by(|a, b| b.1.rating.total_cmp(&a.1.rating).then(a.0.cmp(&b.0)));
+		ratings
+	}
+
+	pub fn head_to_head(self: &Self, agent_id: i32, opponent_id: i32) -> HeadToHead {
+		let (wins, losses, draws) = if agent_id < opponent_id {
+			self.records.get(&(agent_id, opponent_id)).copied().unwrap_or((0, 0, 0))
+		} else {
+			let (wins, losses, draws) = self.records.get(&(opponent_id, agent_id)).copied().unwrap_or((0, 0, 0));
+			(losses, wins, draws)
+		};
+		HeadToHead {
+			agent_id,
+			opponent_id,
+			wins,
+			losses,
+			draws,
+			expected_score: self.system.expected_score(&self.rating(agent_id), &self.rating(opponent_id)),
+		}
+	}
+
+	// every agent that agent_id has played
+	pub fn opponents(self: &Self, agent_id: i32) -> Vec<i32> {
+		let mut opponents = self.records.keys()
+			.filter_map(|(lower, higher)| if *lower == agent_id { Some(*higher) } else if *higher == agent_id { Some(*lower) } else { None })
+			.collect::<Vec<i32>>();
+		opponents.sort();
+		opponents
+	}
+
+	pub fn to_json(self: &Self) -> String {
+		let ratings = self.ratings().into_iter()
+			.map(|(id, rating)| json!({
+				"agent_id": id, "rating": rating.rating, "deviation": rating.deviation, "matches": rating.matches,
+				"wins": rating.wins, "losses": rating.losses, "draws": rating.draws,
+			}))
+			.collect::<Vec<Value>>();
+		let records = self.records.iter()
+			.map(|((lower, higher), (wins, losses, draws))| json!({
+				"agents": [lower, higher], "wins": wins, "losses": losses, "draws": draws,
+			}))
+			.collect::<Vec<Value>>();
+		json!({"system": self.system.name(), "ratings": ratings, "records": records}).to_string()
+	}
+
+	pub fn from_json(bytes: &[u8], system: RatingSystem) -> Result<RatingTable, String> {
+		let table: Value = serde_json::from_slice(bytes).map_err(|err| err.to_string())?;
+		if table["system"].as_str() != Some(system.name()) {
+			return Err(format!("ratings were computed with {:?}, not {}", table["system"], system.name()));
+		}
+		let mut ratings = HashMap::new();
+		for entry in table["ratings"].as_array().cloned().unwrap_or_default() {
+			ratings.insert(json_store::int(&entry, "agent_id")? as i32, Rating {
+				rating: json_store::float(&entry, "rating")?,
+				deviation: json_store::float(&entry, "deviation")?,
+				matches: json_store::int(&entry, "matches")? as u32,
+				wins: json_store::int(&entry, "wins")? as u32,
+				losses: json_store::int(&entry, "losses")? as u32,
+				draws: json_store::int(&entry, "draws")? as u32,
+			});
+		}
+		let mut records = HashMap::new();
+		for entry in table["records"].as_array().cloned().unwrap_or_default() {
+			let agents = entry["agents"].as_array()
+				.filter(|agents| agents.len() == 2)
+				.ok_or_else(|| "head to head entry has no agents".to_string())?;
+			let lower = agents[0].as_i64().ok_or_else(|| "head to head agent is not an id".to_string())? as i32;
+			let higher = agents[1].as_i64().ok_or_else(|| "head to head agent is not an id".to_string())? as i32;
+			records.insert((lower, higher), (json_store::int(&entry, "wins")? as u32, json_store::int(&entry, "losses")? as u32, json_store::int(&entry, "draws")? as u32));
+		}
+		Ok(RatingTable {
+			system,
+			ratings,
+			records,
+		})
+	}
+}
+
+// RatingTable shared by every rollout and persisted as JSON at path
+pub struct RatingStore {
+	path: PathBuf,
+	table: Mutex<RatingTable>,
+}
+
+impl RatingStore {
+	pub fn open<P: AsRef<Path>>(path: P, system: RatingSystem) -> io::Result<Self> {
+		let path = path.as_ref().to_path_buf();
+		let table = json_store::load(&path, |bytes| RatingTable::from_json(bytes, system), || RatingTable::new(system))?;
+		Ok(RatingStore {
+			path,
+			table: Mutex::new(table),
+		})
+	}
+
+	pub fn record(self: &Self, replay: &MatchReplay) {
+		self.table.lock().unwrap().record(replay.agent_ids, replay.outcome);
+	}
+
+	pub fn snapshot(self: &Self) -> RatingTable {
+		self.table.lock().unwrap().clone()
+	}
+
+	pub fn save(self: &Self) -> io::Result<()> {
+		let json = self.table.lock().unwrap().to_json();
+		json_store::save(&self.path, json)
+	}
+
+	// ratings of agent_ids, or of every rated agent when it is empty
+	pub fn ratings(self: &Self, agent_ids: &[i32]) -> Vec<(i32, Rating)> {
+		let table = self.table.lock().unwrap();
+		if agent_ids.is_empty() {
+			return table.ratings();
+		}
+		agent_ids.iter().map(|id| (*id, table.rating(*id))).collect()
+	}
+
+	// agent_id's record against opponent_ids, or against everyone it has played when it is empty
+	pub fn head_to_head(self: &Self, agent_id: i32, opponent_ids: &[i32]) -> Vec<HeadToHead> {
+		let table = self.table.lock().unwrap();
+		let opponents = if opponent_ids.is_empty() { table.opponents(agent_id) } else { opponent_ids.to_vec() };
+		opponents.into_iter().map(|opponent_id| table.head_to_head(agent_id, opponent_id)).collect()
+	}
+
+	pub fn system(self: &Self) -> RatingSystem {
+		self.table.lock().unwrap().system()
+	}
+}
+
+fn normal_pdf(x: f64) -> f64 {
+	(-x * x / 2.0).exp() / (2.0 * std::f64::consts::PI).sqrt()
+}
+
+fn normal_cdf(x: f64) -> f64 {
+	0.5 * (1.0 + erf(x / 2f64.sqrt()))
+}
+
+// bisection is plenty for the one constant it is used for
+fn inverse_normal_cdf(p: f64) -> f64 {
+	let (mut low, mut high) = (-10.0f64, 10.0f64);
+	for _ in 0..100 {
+		let mid = (low + high) / 2.0;
+		if normal_cdf(mid) < p {
+			low = mid;
+		} else {
+			high = mid;
+		}
+	}
+	(low + high) / 2.0
+}
+
+// Abramowitz and Stegun 7.1.26, accurate to about 1e-7
+fn erf(x: f64) -> f64 {
+	let t = 1.0 / (1.0 + 0.3275911 * x.abs());
+	let poly = t * (0.254829592 + t * (-0.284496736 + t * (1.421413741 + t * (-1.453152027 + t * 1.061405429))));
+	let value = 1.0 - poly * (-x * x).exp();
+	if x < 0.0 { -value } else { value }
+}
@@ -0,0 +1,290 @@
+use std::collections::HashMap;
+
+use rand::{Rng, thread_rng};
+use rand::rngs::ThreadRng;
+
+use engine::engine::{Engine, EngineConfig};
+use engine::state::{Action, Direction, FruitType, GameState, PlayerIdx, PlayerWinner, WinState};
+
+use crate::ai::AiPlayer;
+use crate::error::SurgeError;
+
+const MOVES: [Direction; 4] = [Direction::Up, Direction::Down, Direction::Left, Direction::Right];
+const FRUIT_TYPES: [FruitType; 3] = [FruitType::Apple, FruitType::Banana, FruitType::Orange];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolverConfig {
+	// a match still going after this many rounds counts as a draw
+	pub max_rounds: u32,
+	// solving stops with an error once the table holds this many states
+	pub max_states: usize,
+}
+
+impl Default for SolverConfig {
+	fn default() -> Self {
+		SolverConfig {
+			max_rounds: 20,
+			max_states: 1_000_000,
+		}
+	}
+}
+
+// equilibrium of the game from one state, values are from player 1's side:
+// 1 is a certain win, -1 a certain loss and draws and timeouts are 0
+#[derive(Debug, Clone, PartialEq)]
+pub struct Solution {
+	pub value: f32,
+	// each seat's legal moves with their equilibrium probabilities
+	pub policies: (Vec<(Action, f32)>, Vec<(Action, f32)>),
+	// value of every pair of moves, rows are player 1's moves and columns player 2's
+	pub payoffs: Vec<Vec<f32>>,
+}
+
+impl Solution {
+	pub fn policy(self: &Self, seat: PlayerIdx) -> &Vec<(Action, f32)> {
+		match seat {
+			PlayerIdx::Player1 => &self.policies.0,
+			PlayerIdx::Player2 => &self.policies.1,
+		}
+	}
+
+	// how much a seat gives up by playing policy (Up, Down, Left, Right probabilities) here instead
+	// of the equilibrium, against an opponent that answers it as well as possible
+	pub fn gap(self: &Self, seat: PlayerIdx, policy: &[f32]) -> f32 {
+		let moves = |policies: &Vec<(Action, f32)>| policies.iter()
+			.map(|(action, _)| match action {
+				Action::Move(direction) => policy.get(*direction as usize).copied().unwrap_or(0.0f32),
+				Action::DoNothing => 0.0f32,
+			})
+			.collect::<Vec<f32>>();
+		let worst_case = match seat {
+			PlayerIdx::Player1 => {
+				let weights = moves(&self.policies.0);
+				(0..self.policies.1.len())
+					.map(|col| self.payoffs.iter().zip(weights.iter()).map(|(row, weight)| row[col] * weight).sum::<f32>())
+					.fold(f32::INFINITY, f32::min)
+			}
+			PlayerIdx::Player2 => {
+				let weights = moves(&self.policies.1);
+				-self.payoffs.iter()
+					.map(|row| row.iter().zip(weights.iter()).map(|(payoff, weight)| payoff * weight).sum::<f32>())
+					.fold(f32::NEG_INFINITY, f32::max)
+			}
+		};
+		let value = match seat {
+			PlayerIdx::Player1 => self.value,
+			PlayerIdx::Player2 => -self.value,
+		};
+		(value - worst_case).max(0.0f32)
+	}
+}
+
+// everything the rest of a match depends on, the fruit counts only matter through who leads each category
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct StateKey {
+	positions: ((i8, i8), (i8, i8)),
+	fruit: Vec<u8>,
+	// player 1's lead per fruit type, in half fruit
+	leads: [i32; 3],
+	round: u32,
+}
+
+impl StateKey {
+	fn new(state: &GameState) -> Self {
+		let lead = |fruit: &FruitType| ((state.player1.get_fruit_count(*fruit) - state.player2.get_fruit_count(*fruit)) * 2.0f32).round() as i32;
+		StateKey {
+			positions: ((state.player1.position.x, state.player1.position.y), (state.player2.position.x, state.player2.position.y)),
+			fruit: state.board.fruit_map.iter().map(|cell| cell.map(|fruit| fruit as u8).unwrap_or(0)).collect(),
+			leads: [lead(&FRUIT_TYPES[0]), lead(&FRUIT_TYPES[1]), lead(&FRUIT_TYPES[2])],
+			round: state.round,
+		}
+	}
+}
+
+// Solves the simultaneous-move game below a state by backward induction over every reachable
+// state up to max_rounds, sharing the states reached along different paths. Each state is a
+// matrix game over the two players' legal moves whose entries are the values of the states
+// they lead to, solved exactly as a linear program.
+pub struct Solver {
+	conf: SolverConfig,
+	// stepped from each state, its history is cleared before every step
+	engine: Engine,
+	table: HashMap<StateKey, Solution>,
+}
+
+impl Solver {
+	pub fn new(engine_config: EngineConfig, conf: SolverConfig) -> Self {
+		Solver {
+			conf,
+			engine: Engine::new(engine_config),
+			table: HashMap::new(),
+		}
+	}
+
+	pub fn states_solved(self: &Self) -> usize {
+		self.table.len()
+	}
+
+	pub fn solve(self: &mut Self, state: &GameState) -> Result<Solution, SurgeError> {
+		let value = match state.match_status {
+			WinState::Finished(PlayerWinner::Player1) => Some(1.0f32),
+			WinState::Finished(PlayerWinner::Player2) => Some(-1.0f32),
+			WinState::Draw | WinState::Timeout => Some(0.0f32),
+			WinState::InProgress if state.round >= self.conf.max_rounds => Some(0.0f32),
+			WinState::InProgress => None,
+		};
+		if let Some(value) = value {
+			return Ok(Solution { value, policies: (vec![], vec![]), payoffs: vec![] });
+		}
+
+		let key = StateKey::new(state);
+		if let Some(solution) = self.table.get(&key) {
+			return Ok(solution.clone());
+		}
+		if self.table.len() >= self.conf.max_states {
+			return Err(SurgeError::InvalidConfig(format!("the game has more than {} states to solve", self.conf.max_states)));
+		}
+
+		let moves = (Solver::legal_moves(state, PlayerIdx::Player1), Solver::legal_moves(state, PlayerIdx::Player2));
+		let mut payoffs = vec![vec![0.0f32; moves.1.len()]; moves.0.len()];
+		for (row, move1) in moves.0.iter().enumerate() {
+			for (col, move2) in moves.1.iter().enumerate() {
+				let next_state = self.step(state, (*move1, *move2));
+				payoffs[row][col] = self.solve(&next_state)?.value;
+			}
+		}
+		let (value, policy1, policy2) = solve_matrix_game(&payoffs);
+		let solution = Solution {
+			value,
+			policies: (moves.0.into_iter().zip(policy1).collect(), moves.1.into_iter().zip(policy2).collect()),
+			payoffs,
+		};
+		self.table.insert(key, solution.clone());
+		Ok(solution)
+	}
+
+	fn legal_moves(state: &GameState, seat: PlayerIdx) -> Vec<Action> {
+		let action_mask = GameState::get_action_mask_vec(state, seat);
+		MOVES.iter()
+			.zip(action_mask.iter())
+			.filter(|(_, invalid)| **invalid == 0.0f32)
+			.map(|(direction, _)| Action::Move(*direction))
+			.collect()
+	}
+
+	fn step(self: &mut Self, state: &GameState, actions: (Action, Action)) -> GameState {
+		self.engine.current_state = state.clone();
+		self.engine.game_history.clear();
+		self.engine.apply_move(actions, None);
+		self.engine.current_state.clone()
+	}
+}
+
+// plays the equilibrium policy of whichever seat it is in
+pub struct SolverPlayer {
+	solver: Solver,
+	rng: ThreadRng,
+}
+
+impl SolverPlayer {
+	pub fn new(solver: Solver) -> Self {
+		SolverPlayer {
+			solver,
+			rng: thread_rng(),
+		}
+	}
+
+	// solves every state reachable from the start of the match, so a game over max_states fails
+	// before the first move instead of partway through, and each move is a table lookup
+	pub fn check_states(self: &mut Self, state: &GameState) -> Result<(), SurgeError> {
+		self.solver.solve(state).map(|_| ())
+	}
+}
+
+impl AiPlayer for SolverPlayer {
+	fn get_move(self: &mut Self, current_state: &GameState, seat: PlayerIdx) -> Result<Action, SurgeError> {
+		let solution = self.solver.solve(current_state)?;
+		let mut threshold = self.rng.gen::<f32>();
+		let policy = solution.policy(seat);
+		Ok(policy.iter()
+			.find(|(_, prob)| {
+				threshold -= prob;
+				threshold < 0.0f32
+			})
+			.or_else(|| policy.last())
+			.map(|(action, _)| *action)
+			.unwrap_or(Action::DoNothing))
+	}
+}
+
+// value and optimal mixed strategies of the zero-sum game where the row player gets payoffs[row][col].
+// With every payoff shifted to be positive, maximising sum(y) subject to payoffs * y <= 1, y >= 0
+// gives the column strategy y / sum(y) and the value 1 / sum(y), the duals of its constraints
+// give the row strategy. Solved with the simplex method using Bland's rule.
+pub fn solve_matrix_game(payoffs: &[Vec<f32>]) -> (f32, Vec<f32>, Vec<f32>) {
+	let rows = payoffs.len();
+	let cols = payoffs[0].len();
+	let min = payoffs.iter().flatten().cloned().fold(f32::INFINITY, f32::min) as f64;
+	let shift = 1.0f64 - min;
+
+	// constraint rows of [payoffs | slack | rhs] and the objective row below them
+	let width = cols + rows + 1;
+	let mut tableau = vec![vec![0.0f64; width]; rows + 1];
+	for (row, payoff_row) in payoffs.iter().enumerate() {
+		for (col, payoff) in payoff_row.iter().enumerate() {
+			tableau[row][col] = *payoff as f64 + shift;
+		}
+		tableau[row][cols + row] = 1.0f64;
+		tableau[row][width - 1] = 1.0f64;
+	}
+	for col in 0..cols {
+		tableau[rows][col] = -1.0f64;
+	}
+	let mut basis = (cols..cols + rows).collect::<Vec<usize>>();
+
+	const EPS: f64 = 1e-9;
+	while let Some(entering) = (0..width - 1).find(|col| tableau[rows][*col] < -EPS) {
+		let leaving = (0..rows)
+			.filter(|row| tableau[*row][entering] > EPS)
+			.min_by(|a, b| {
+				let ratio_a = tableau[*a][width - 1] / tableau[*a][entering];
+				let ratio_b = tableau[*b][width - 1] / tableau[*b][entering];
+				ratio_a.total_cmp(&ratio_b).then(basis[*a].cmp(&basis[*b]))
+			});
+		// bounded by construction, every column has a positive entry
+		let leaving = match leaving {
+			Some(leaving) => leaving,
+			None => break,
+		};
+		let pivot = tableau[leaving][entering];
+		for value in tableau[leaving].iter_mut() {
+			*value /= pivot;
+		}
+		for row in 0..=rows {
+			let factor = tableau[row][entering];
+			if row != leaving && factor != 0.0f64 {
+				for col in 0..width {
+					tableau[row][col] -= factor * tableau[leaving][col];
+				}
+			}
+		}
+		basis[leaving] = entering;
+	}
+
+	let total = tableau[rows][width - 1];
+	let mut col_strategy = vec![0.0f32; cols];
+	for (row, var) in basis.iter().enumerate() {
+		if *var < cols {
+			col_strategy[*var] = (tableau[row][width - 1] / total) as f32;
+		}
+	}
+	let row_strategy = (0..rows)
+		.map(|row| (tableau[rows][cols + row] / total).max(0.0f64) as f32)
+		.collect::<Vec<f32>>();
+	(((1.0f64 / total) - shift) as f32, normalise(row_strategy), normalise(col_strategy))
+}
+
+fn normalise(probs: Vec<f32>) -> Vec<f32> {
+	let sum = probs.iter().sum::<f32>();
+	probs.iter().map(|prob| prob / sum).collect()
+}
@@ -1,16 +1,37 @@
 #[cfg(test)]
 mod tests {
 	use std::collections::HashMap;
+	use std::sync::Arc;
+	use std::sync::atomic::{AtomicBool, Ordering};
 	use engine::engine::{Engine, EngineConfig, FruitType, Direction, Action};
 	use rand::{thread_rng, seq};
 	use rand::seq::IteratorRandom;
 
+	use engine::observation::EncoderKind;
+	use engine::state::{GameState, MatchReplay, PlayerIdx, PlayerWinner, Position, WinState};
+
+	use crate::ai::{AiPlayer, BlockerPlayer, ContestedPlayer, GreedyPlayer, ScriptedOpponent};
+	use crate::advantage::{gae, replay_advantages, AdvantageConfig};
+	use crate::error::SurgeError;
+	use crate::inference::{InferenceBackend, ModelFormat, ModelOutputs, OutputLayout, OutputSpec, Policy, ALL_LOGITS};
+	use crate::jobs::{JobId, JobQueue, JobState, JobStatus};
+	use crate::league::{AgentRole, League, LeagueConfig, LeagueStore};
+	use crate::manager::{MatchMode, RolloutConfig, RolloutManager};
+	use crate::matchmaking::{MatchmakingKind, MatchmakingPool, PfspStrategy};
+	use crate::mcts::{MctsConfig, MctsPlayer};
+	use crate::mlp::MlpBackend;
+	use crate::model_store::ModelStore;
+	use crate::ratings::{RatingStore, RatingSystem, RatingTable};
+	use crate::solver::{solve_matrix_game, Solver, SolverConfig, SolverPlayer};
+	use crate::worker::{mask_logits, RolloutWorker};
+
 	fn blank_engine() -> Engine {
 		Engine::new(EngineConfig {
 			board_size: 5,
 			populate_board: true,
 			fruit_density: 0.3f32,
 			random_seed: 123,
+			..EngineConfig::default()
 		})
 	}
 
@@ -19,13 +40,841 @@ mod tests {
 		let mut engine = blank_engine();
 
 		for i in 0..100 {
-			let moves = (engine.get_valid_moves(&engine.current_state.player1),
-						 engine.get_valid_moves(&engine.current_state.player2));
+			let moves = (engine.current_state.get_valid_moves(&engine.current_state.player1),
+						 engine.current_state.get_valid_moves(&engine.current_state.player2));
 
 			let random_move2 = *moves.1.iter().choose(&mut rng).unwrap();
 			let random_move1 = *moves.0.iter().choose(&mut rng).unwrap();
-			engine.apply_move((random_move1, random_move2));
+			engine.apply_move((random_move1, random_move2), None);
 		}
 		println!("{:?}", engine.check_gameover());
 	}
+
+	fn rollout_config(match_mode: MatchMode, opponent_ids: Vec<i32>) -> RolloutConfig {
+		RolloutConfig {
+			engine_config: EngineConfig::default(),
+			agent_ids: (1, opponent_ids),
+			max_rounds: 100,
+			evaluation_mode: false,
+			max_matches: 4,
+			batch_size: 1,
+			match_mode,
+			matchmaking: MatchmakingKind::Uniform,
+			scripted_opponent: ScriptedOpponent::Random,
+			mcts: MctsConfig::default(),
+			observation: EncoderKind::Flat { pad_to: None },
+			record_logits: false,
+		}
+	}
+
+	#[test]
+	fn validate_rejects_empty_pool() {
+		assert!(rollout_config(MatchMode::VsRandom, vec![]).validate().is_ok());
+		assert!(rollout_config(MatchMode::VsPool, vec![2]).validate().is_ok());
+
+		let err = rollout_config(MatchMode::VsPool, vec![]).validate().unwrap_err();
+		assert!(matches!(err, SurgeError::InvalidConfig(_)));
+		let err = rollout_config(MatchMode::Mixed(1.5f32), vec![2]).validate().unwrap_err();
+		assert!(matches!(err, SurgeError::InvalidConfig(_)));
+	}
+
+	fn assert_close(left: &[f32], right: &[f32]) {
+		assert_eq!(left.len(), right.len());
+		for (l, r) in left.iter().zip(right) {
+			assert!((l - r).abs() < 1e-5, "{:?} != {:?}", left, right);
+		}
+	}
+
+	#[test]
+	fn gae_discounts_rewards() {
+		let conf = AdvantageConfig { gamma: 0.9f32, lambda: 1.0f32 };
+		let (advantages, returns) = gae(&[0.0f32, 0.0f32, 1.0f32], &[0.0f32; 3], 0.0f32, &conf);
+		assert_close(&returns, &[0.81f32, 0.9f32, 1.0f32]);
+		assert_close(&advantages, &returns);
+
+		// lambda 0 is the one step TD error, bootstrapped from the value after the last step
+		let conf = AdvantageConfig { gamma: 0.5f32, lambda: 0.0f32 };
+		let (advantages, returns) = gae(&[1.0f32, 1.0f32], &[2.0f32, 4.0f32], 8.0f32, &conf);
+		assert_close(&advantages, &[1.0f32, 1.0f32]);
+		assert_close(&returns, &[3.0f32, 5.0f32]);
+
+		assert!(AdvantageConfig { gamma: 1.5f32, lambda: 0.9f32 }.validate().is_err());
+	}
+
+	#[test]
+	fn gae_bootstraps_only_truncated_replays() {
+		let mut engine = blank_engine();
+		engine.apply_move((Action::DoNothing, Action::DoNothing), None);
+		engine.record_values((1.0f32, 2.0f32));
+		engine.truncate();
+
+		let conf = AdvantageConfig { gamma: 1.0f32, lambda: 1.0f32 };
+		let mut replay = MatchReplay::new(engine.game_history.clone(), (1, 2), engine.current_state.match_status);
+		replay.bootstrap_values = (3.0f32, 5.0f32);
+		let (_, returns) = replay_advantages(&replay, PlayerIdx::Player2, &conf);
+		assert_close(&returns, &[-0.1f32 + 5.0f32]);
+
+		let replay = MatchReplay { outcome: WinState::Draw, ..replay };
+		let (advantages, returns) = replay_advantages(&replay, PlayerIdx::Player1, &conf);
+		assert_close(&returns, &[-0.1f32]);
+		assert_close(&advantages, &[-1.1f32]);
+	}
+
+	fn wait_for_job(jobs: &JobQueue, job_id: JobId, done: impl Fn(&JobStatus) -> bool) -> JobStatus {
+		let deadline = std::time::Instant::now() + std::time::Duration::from_secs(5);
+		loop {
+			let status = jobs.status(job_id).unwrap();
+			if done(&status) {
+				return status;
+			}
+			if std::time::Instant::now() > deadline {
+				panic!("job never got there, last status {:?}", status);
+			}
+			std::thread::sleep(std::time::Duration::from_millis(5));
+		}
+	}
+
+	#[test]
+	fn job_queue_reports_failed_jobs() {
+		let model_dir = std::env::temp_dir().join(format!("surge-jobs-{}", std::process::id()));
+		std::fs::create_dir_all(&model_dir).unwrap();
+		let model_store = Arc::new(ModelStore::new(model_dir, 4, 0));
+		let jobs = JobQueue::new(1, 2).unwrap();
+
+		let manager = RolloutManager::new(rollout_config(MatchMode::VsRandom, vec![]), model_store, 1);
+		let job_id = jobs.submit(manager).unwrap().job_id;
+		let status = wait_for_job(&jobs, job_id, JobStatus::finished);
+		// there is no model 1 in the store
+		assert_eq!(status.state, JobState::Failed);
+		assert_eq!(status.error, Some(SurgeError::ModelNotFound(1)));
+
+		let (_, replays) = jobs.fetch_results(job_id).unwrap();
+		assert!(replays.is_empty());
+		assert_eq!(jobs.status(job_id).unwrap_err(), SurgeError::JobNotFound(job_id));
+		assert_eq!(jobs.cancel(job_id + 1).unwrap_err(), SurgeError::JobNotFound(job_id + 1));
+	}
+
+	#[test]
+	fn vs_pool_matches_draw_opponents_from_the_pool() {
+		let model_dir = std::env::temp_dir().join(format!("surge-vs-pool-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		for id in 1..=3 {
+			model_store.save_model(id, ModelFormat::SafeTensors, OutputSpec::default(), &symbolic_mlp_bytes()).unwrap();
+		}
+		let mut conf = rollout_config(MatchMode::VsPool, vec![2, 3]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.max_matches = 6;
+		conf.max_rounds = 10;
+
+		let replays = RolloutManager::new(conf, model_store, 2).play_matches().unwrap();
+		assert_eq!(replays.len(), 6);
+		for replay in replays.iter() {
+			assert_eq!(replay.agent_ids.0, 1);
+			assert!([2, 3].contains(&replay.agent_ids.1), "{:?} is not in the pool", replay.agent_ids);
+		}
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn record_logits_keeps_masked_logits() {
+		let model_dir = std::env::temp_dir().join(format!("surge-logits-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		// always heads up, so it soon runs into the edge of the board
+		let bytes = safetensors_bytes(&[
+			("0.weight", vec![2, 22], vec![0.0; 44]),
+			("0.bias", vec![2], vec![0.0; 2]),
+			("2.weight", vec![5, 2], vec![0.0; 10]),
+			("2.bias", vec![5], vec![1.0, 0.0, 0.0, 0.0, 0.0]),
+		]);
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &bytes).unwrap();
+		let mut conf = rollout_config(MatchMode::VsRandom, vec![]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.evaluation_mode = true;
+		conf.max_matches = 2;
+		conf.max_rounds = 30;
+		conf.record_logits = true;
+
+		let replays = RolloutWorker::new(conf.clone(), model_store.clone()).play_matches().unwrap();
+		let sars = replays.iter().flat_map(|replay| replay.sars.iter()).collect::<Vec<_>>();
+		for sar in sars.iter() {
+			let action_mask = GameState::get_action_mask_vec(&sar.gamestate, PlayerIdx::Player1);
+			assert_eq!(sar.logits.0, mask_logits(vec![1.0, 0.0, 0.0, 0.0], &action_mask));
+			// the scripted opponent has no logits to record
+			assert!(sar.logits.1.is_empty());
+		}
+		assert!(sars.iter().any(|sar| sar.logits.0[0] == f32::NEG_INFINITY));
+
+		conf.record_logits = false;
+		let replays = RolloutWorker::new(conf, model_store).play_matches().unwrap();
+		assert!(replays.iter().flat_map(|replay| replay.sars.iter()).all(|sar| sar.logits.0.is_empty()));
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn max_matches_split_across_workers() {
+		let model_dir = std::env::temp_dir().join(format!("surge-split-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &symbolic_mlp_bytes()).unwrap();
+		let mut conf = rollout_config(MatchMode::VsRandom, vec![]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.max_matches = 10;
+
+		let manager = RolloutManager::new(conf.clone(), model_store.clone(), 4);
+		let configs = manager.worker_configs();
+		assert_eq!(configs.iter().map(|conf| conf.max_matches).collect::<Vec<u32>>(), vec![3, 3, 2, 2]);
+		let seeds = configs.iter().map(|conf| conf.engine_config.random_seed).collect::<Vec<u64>>();
+		assert!(seeds.iter().enumerate().all(|(idx, seed)| !seeds[..idx].contains(seed)));
+		assert_eq!(manager.play_matches().unwrap().len(), 10);
+
+		// no thread is started without a match to play
+		conf.max_matches = 2;
+		let manager = RolloutManager::new(conf, model_store, 4);
+		assert_eq!(manager.worker_configs().len(), 2);
+		assert_eq!(manager.play_matches().unwrap().len(), 2);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn stream_matches_sends_replays_as_they_finish() {
+		let model_dir = std::env::temp_dir().join(format!("surge-stream-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &symbolic_mlp_bytes()).unwrap();
+		let mut conf = rollout_config(MatchMode::VsRandom, vec![]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.max_matches = 100_000;
+		let manager = RolloutManager::new(conf, model_store, 2);
+
+		let (sink, replays) = std::sync::mpsc::channel();
+		let stop = Arc::new(AtomicBool::new(false));
+		let worker_stop = stop.clone();
+		let rollout = std::thread::spawn(move || manager.stream_matches(sink, worker_stop));
+		// each replay comes through on its own while the rest of the run is still playing
+		for _ in 0..3 {
+			let replay = replays.recv_timeout(std::time::Duration::from_secs(5)).unwrap();
+			assert_eq!(replay.agent_ids, (1, ScriptedOpponent::Random.agent_id()));
+			assert!(!rollout.is_finished());
+		}
+		stop.store(true, Ordering::Relaxed);
+		// the stream ends once every worker sees the stop flag
+		let rest = replays.iter().count();
+		assert!(rollout.join().unwrap().is_ok());
+		assert!(3 + rest < 100_000);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn job_queue_cancels_jobs() {
+		let model_dir = std::env::temp_dir().join(format!("surge-cancel-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &symbolic_mlp_bytes()).unwrap();
+		let mut conf = rollout_config(MatchMode::VsRandom, vec![]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.max_matches = 100_000;
+		// one runner, so the second job waits for the first
+		let jobs = JobQueue::new(1, 2).unwrap();
+
+		let running = jobs.submit(RolloutManager::new(conf.clone(), model_store.clone(), 1)).unwrap().job_id;
+		wait_for_job(&jobs, running, |status| status.state == JobState::Running);
+		let queued = jobs.submit(RolloutManager::new(conf, model_store, 1)).unwrap().job_id;
+		assert_eq!(jobs.status(queued).unwrap().state, JobState::Queued);
+		assert_eq!(jobs.cancel(queued).unwrap().state, JobState::Cancelled);
+
+		jobs.cancel(running).unwrap();
+		let status = wait_for_job(&jobs, running, JobStatus::finished);
+		assert_eq!(status.state, JobState::Cancelled);
+		assert!(status.completed < status.total);
+		let (_, replays) = jobs.fetch_results(running).unwrap();
+		assert_eq!(replays.len(), status.completed as usize);
+
+		// the runner skips the cancelled job instead of playing it
+		let (status, replays) = jobs.fetch_results(queued).unwrap();
+		assert_eq!((status.state, status.completed), (JobState::Cancelled, 0));
+		assert!(replays.is_empty());
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	fn safetensors_bytes(tensors: &[(&str, Vec<usize>, Vec<f32>)]) -> Vec<u8> {
+		let mut header = vec![];
+		let mut data = vec![];
+		for (name, shape, values) in tensors {
+			let begin = data.len();
+			for value in values {
+				data.extend_from_slice(&value.to_le_bytes());
+			}
+			header.push(format!("\"{}\":{{\"dtype\":\"F32\",\"shape\":{:?},\"data_offsets\":[{},{}]}}",
+								name, shape, begin, data.len()));
+		}
+		let header = format!("{{{}}}", header.join(","));
+		let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+		bytes.extend_from_slice(header.as_bytes());
+		bytes.extend_from_slice(&data);
+		bytes
+	}
+
+	fn mlp_bytes() -> Vec<u8> {
+		safetensors_bytes(&[
+			("2.weight", vec![5, 2], vec![1.0, 0.0, 0.0, 1.0, 2.0, 0.0, 0.0, 0.0, 3.0, 1.0]),
+			("2.bias", vec![5], vec![0.0, 0.0, 0.0, 0.0, 0.5]),
+			("0.weight", vec![2, 3], vec![1.0, 0.0, 0.0, 0.0, 1.0, 0.0]),
+			("0.bias", vec![2], vec![0.0, 0.0]),
+		])
+	}
+
+	// an untrained model for the 22 symbolic features, every move is equally likely
+	fn symbolic_mlp_bytes() -> Vec<u8> {
+		safetensors_bytes(&[
+			("0.weight", vec![2, 22], vec![0.0; 44]),
+			("0.bias", vec![2], vec![0.0; 2]),
+			("2.weight", vec![5, 2], vec![0.0; 10]),
+			("2.bias", vec![5], vec![0.0; 5]),
+		])
+	}
+
+	#[test]
+	fn mlp_backend_forward() {
+		let mlp = MlpBackend::from_safetensors(&mlp_bytes()).unwrap();
+		assert_eq!(mlp.input_size(), 3);
+
+		// the second hidden unit is cut by relu
+		let outputs = mlp.forward(&[vec![1.0, -1.0, 2.0], vec![0.0, 2.0, 0.0]]).unwrap();
+		assert_eq!(outputs, vec![vec![vec![1.0, 0.0, 2.0, 0.0, 3.5], vec![0.0, 2.0, 0.0, 0.0, 2.5]]]);
+		assert!(mlp.forward(&[vec![1.0]]).is_err());
+		assert_eq!(mlp.forward(&[]).unwrap(), ModelOutputs::new());
+
+		let missing_bias = safetensors_bytes(&[("0.weight", vec![2, 3], vec![0.0; 6])]);
+		assert!(MlpBackend::from_safetensors(&missing_bias).is_err());
+		assert!(MlpBackend::from_safetensors(&[1, 2, 3]).is_err());
+	}
+
+	#[test]
+	fn model_store_serves_safetensors() {
+		let model_dir = std::env::temp_dir().join(format!("surge-mlp-{}", std::process::id()));
+		let model_store = ModelStore::new(&model_dir, 4, 0);
+		let bytes = mlp_bytes();
+
+		let err = model_store.save_model(3, ModelFormat::SafeTensors, OutputSpec::default(), &bytes[..10]).unwrap_err();
+		assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+		model_store.save_model(3, ModelFormat::SafeTensors, OutputSpec::default(), &bytes).unwrap();
+		assert_eq!(model_store.list_models().unwrap(), vec![(3, ModelFormat::SafeTensors, bytes.len() as u64)]);
+
+		let model = model_store.get(3).unwrap();
+		assert_eq!(model.evaluate(&[vec![1.0, -1.0, 2.0]]).unwrap(), vec![(vec![1.0, 0.0, 2.0, 0.0], 3.5)]);
+		assert!(model.evaluate(&[]).unwrap().is_empty());
+
+		// the same weights read as five logits without a value head
+		let spec = OutputSpec { layout: OutputLayout::Concatenated, num_logits: ALL_LOGITS, value_head: false };
+		model_store.save_model(3, ModelFormat::SafeTensors, spec, &bytes).unwrap();
+		assert_eq!(model_store.output_spec(3).unwrap(), spec);
+		assert_eq!(model_store.get(3).unwrap().evaluate(&[vec![1.0, -1.0, 2.0]]).unwrap(), vec![(vec![1.0, 0.0, 2.0, 0.0, 3.5], 0.0)]);
+		assert!(model_store.delete_model(3).unwrap());
+		assert_eq!(model_store.output_spec(3).unwrap(), OutputSpec::default());
+		assert!(matches!(model_store.get(3), Err(SurgeError::ModelNotFound(3))));
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn model_store_evicts_least_recently_used() {
+		let model_dir = std::env::temp_dir().join(format!("surge-evict-count-{}", std::process::id()));
+		let model_store = ModelStore::new(&model_dir, 2, 0);
+		for id in 1..=3 {
+			model_store.save_model(id, ModelFormat::SafeTensors, OutputSpec::default(), &mlp_bytes()).unwrap();
+		}
+		model_store.get(1).unwrap();
+		model_store.get(2).unwrap();
+		model_store.get(1).unwrap();
+		// 2 is the least recently used once 3 comes in
+		model_store.get(3).unwrap();
+		assert_eq!(model_store.cached_ids(), vec![1, 3]);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn model_store_evicts_by_size() {
+		let model_dir = std::env::temp_dir().join(format!("surge-evict-bytes-{}", std::process::id()));
+		let size = mlp_bytes().len() as u64;
+		let model_store = ModelStore::new(&model_dir, 10, 2 * size);
+		for id in 1..=3 {
+			model_store.save_model(id, ModelFormat::SafeTensors, OutputSpec::default(), &mlp_bytes()).unwrap();
+			model_store.get(id).unwrap();
+		}
+		assert_eq!(model_store.cached_ids(), vec![2, 3]);
+
+		// a model over the bound on its own is still served, it just evicts everything else
+		let model_store = ModelStore::new(&model_dir, 10, size - 1);
+		model_store.get(1).unwrap();
+		model_store.get(2).unwrap();
+		assert_eq!(model_store.cached_ids(), vec![2]);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn model_store_reloads_rewritten_models() {
+		let model_dir = std::env::temp_dir().join(format!("surge-reload-{}", std::process::id()));
+		let model_store = ModelStore::new(&model_dir, 4, 0);
+		let model_path = model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &mlp_bytes()).unwrap();
+		let model = model_store.get(1).unwrap();
+		assert!(Arc::ptr_eq(&model, &model_store.get(1).unwrap()));
+
+		// written behind the store's back, only the modification time tells it apart
+		let bytes = safetensors_bytes(&[
+			("2.weight", vec![5, 2], vec![0.0; 10]),
+			("2.bias", vec![5], vec![1.0, 2.0, 3.0, 4.0, 5.0]),
+			("0.weight", vec![2, 3], vec![0.0; 6]),
+			("0.bias", vec![2], vec![0.0, 0.0]),
+		]);
+		std::fs::write(&model_path, &bytes).unwrap();
+		let modified = std::fs::metadata(&model_path).unwrap().modified().unwrap() + std::time::Duration::from_secs(10);
+		std::fs::File::options().write(true).open(&model_path).unwrap().set_modified(modified).unwrap();
+
+		let reloaded = model_store.get(1).unwrap();
+		assert!(!Arc::ptr_eq(&model, &reloaded));
+		assert_eq!(reloaded.evaluate(&[vec![1.0, -1.0, 2.0]]).unwrap(), vec![(vec![1.0, 2.0, 3.0, 4.0], 5.0)]);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn batched_matches_get_distinct_boards() {
+		let model_dir = std::env::temp_dir().join(format!("surge-boards-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &symbolic_mlp_bytes()).unwrap();
+		let mut conf = rollout_config(MatchMode::VsRandom, vec![]);
+		conf.observation = EncoderKind::Symbolic;
+		conf.max_matches = 8;
+		conf.max_rounds = 5;
+		conf.batch_size = 3;
+
+		let replays = RolloutWorker::new(conf, model_store).play_matches().unwrap();
+		let boards = replays.iter()
+			.map(|replay| replay.sars[0].gamestate.board.fruit_map.iter().map(|cell| cell.map(|fruit| fruit as u8).unwrap_or(0)).collect::<Vec<u8>>())
+			.collect::<Vec<Vec<u8>>>();
+		assert_eq!(boards.len(), 8);
+		assert!(boards.iter().enumerate().all(|(idx, board)| !boards[..idx].contains(board)));
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	fn proto_varint(out: &mut Vec<u8>, mut value: u64) {
+		while value >= 0x80 {
+			out.push((value as u8 & 0x7f) | 0x80);
+			value >>= 7;
+		}
+		out.push(value as u8);
+	}
+
+	fn proto_int(out: &mut Vec<u8>, field: u64, value: u64) {
+		proto_varint(out, field << 3);
+		proto_varint(out, value);
+	}
+
+	fn proto_bytes(out: &mut Vec<u8>, field: u64, bytes: &[u8]) {
+		proto_varint(out, field << 3 | 2);
+		proto_varint(out, bytes.len() as u64);
+		out.extend_from_slice(bytes);
+	}
+
+	// [batch, width] f32 tensor type of a graph input or output, batch left symbolic
+	fn onnx_value_info(name: &str, width: u64) -> Vec<u8> {
+		let (mut batch, mut features, mut shape, mut tensor, mut value_type, mut info) = (vec![], vec![], vec![], vec![], vec![], vec![]);
+		proto_bytes(&mut batch, 2, b"batch");
+		proto_int(&mut features, 1, width);
+		proto_bytes(&mut shape, 1, &batch);
+		proto_bytes(&mut shape, 1, &features);
+		proto_int(&mut tensor, 1, 1);
+		proto_bytes(&mut tensor, 2, &shape);
+		proto_bytes(&mut value_type, 1, &tensor);
+		proto_bytes(&mut info, 1, name.as_bytes());
+		proto_bytes(&mut info, 2, &value_type);
+		info
+	}
+
+	fn onnx_initializer(name: &str, dims: &[u64], values: &[f32]) -> Vec<u8> {
+		let mut tensor = vec![];
+		for dim in dims {
+			proto_int(&mut tensor, 1, *dim);
+		}
+		proto_int(&mut tensor, 2, 1);
+		proto_bytes(&mut tensor, 8, name.as_bytes());
+		proto_bytes(&mut tensor, 9, &values.iter().flat_map(|value| value.to_le_bytes()).collect::<Vec<u8>>());
+		tensor
+	}
+
+	fn onnx_node(op_type: &str, inputs: &[&str], output: &str) -> Vec<u8> {
+		let mut node = vec![];
+		for input in inputs {
+			proto_bytes(&mut node, 1, input.as_bytes());
+		}
+		proto_bytes(&mut node, 2, output.as_bytes());
+		proto_bytes(&mut node, 4, op_type.as_bytes());
+		node
+	}
+
+	// the linear layer of mlp_bytes as an ONNX model: y = x * w + b, x is [batch, 3] and y [batch, 5]
+	fn onnx_bytes() -> Vec<u8> {
+		let mut graph = vec![];
+		proto_bytes(&mut graph, 1, &onnx_node("MatMul", &["x", "w"], "h"));
+		proto_bytes(&mut graph, 1, &onnx_node("Add", &["h", "b"], "y"));
+		proto_bytes(&mut graph, 2, b"policy");
+		proto_bytes(&mut graph, 5, &onnx_initializer("w", &[3, 5], &[1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 1.0]));
+		proto_bytes(&mut graph, 5, &onnx_initializer("b", &[5], &[0.0, 0.0, 0.0, 0.0, 0.5]));
+		proto_bytes(&mut graph, 11, &onnx_value_info("x", 3));
+		proto_bytes(&mut graph, 12, &onnx_value_info("y", 5));
+		let mut opset = vec![];
+		proto_int(&mut opset, 2, 13);
+		let mut model = vec![];
+		proto_int(&mut model, 1, 7);
+		proto_bytes(&mut model, 8, &opset);
+		proto_bytes(&mut model, 7, &graph);
+		model
+	}
+
+	#[test]
+	fn onnx_fixture_is_detected() {
+		assert_eq!(ModelFormat::detect(&onnx_bytes()), Some(ModelFormat::Onnx));
+	}
+
+	#[cfg(feature = "onnx")]
+	#[test]
+	fn onnx_backend_forward() {
+		let model_dir = std::env::temp_dir().join(format!("surge-onnx-{}", std::process::id()));
+		let model_store = ModelStore::new(&model_dir, 4, 0);
+		model_store.save_model(5, ModelFormat::Onnx, OutputSpec::default(), &onnx_bytes()).unwrap();
+		let model = model_store.get(5).unwrap();
+
+		assert_eq!(model.evaluate(&[vec![1.0, -1.0, 2.0]]).unwrap(), vec![(vec![1.0, -1.0, 2.0, 0.0], 2.5)]);
+		let outputs = model.evaluate(&[vec![1.0, -1.0, 2.0], vec![0.0, 0.0, 0.0], vec![3.0, 1.0, -1.0]]).unwrap();
+		assert_eq!(outputs, vec![
+			(vec![1.0, -1.0, 2.0, 0.0], 2.5),
+			(vec![0.0, 0.0, 0.0, 0.0], 0.5),
+			(vec![3.0, 1.0, -1.0, 0.0], -0.5),
+		]);
+		assert!(model.evaluate(&[]).unwrap().is_empty());
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn model_format_detection() {
+		assert_eq!(ModelFormat::detect(&mlp_bytes()), Some(ModelFormat::SafeTensors));
+		assert_eq!(ModelFormat::detect(b"PK\x03\x04archive"), Some(ModelFormat::TorchScript));
+		assert_eq!(ModelFormat::detect(&[0x08, 0x07, 0x3a]), Some(ModelFormat::Onnx));
+		assert_eq!(ModelFormat::detect(b"not a model"), None);
+		assert_eq!(ModelFormat::detect(&[0x08, 0x07, 0x42, 0x04]), Some(ModelFormat::Onnx));
+		assert_eq!(ModelFormat::detect(b"\x08garbage"), None);
+		assert_eq!(ModelFormat::detect(&[0x08, 0x07, 0xff]), None);
+		assert_eq!(ModelFormat::from_extension("onnx"), Some(ModelFormat::Onnx));
+		assert_eq!(ModelFormat::from_extension("bin"), None);
+	}
+
+	#[test]
+	fn output_spec_reads_tuple_outputs() {
+		let spec = OutputSpec { layout: OutputLayout::Tuple, num_logits: 4, value_head: true };
+		let outputs = vec![vec![vec![0.1, 0.2, 0.3, 0.4], vec![1.0, 2.0, 3.0, 4.0]], vec![vec![0.5], vec![-1.0]]];
+		assert_eq!(spec.read(&outputs, 2).unwrap(), vec![(vec![0.1, 0.2, 0.3, 0.4], 0.5), (vec![1.0, 2.0, 3.0, 4.0], -1.0)]);
+		// a missing value output and a concatenated row are both rejected
+		assert!(spec.read(&outputs[..1].to_vec(), 2).is_err());
+		assert!(spec.read(&vec![vec![vec![0.1, 0.2, 0.3, 0.4, 0.5]]], 1).is_err());
+		assert!(OutputSpec { num_logits: 3, ..spec }.validate().is_err());
+		assert_eq!(OutputSpec::from_json(spec.to_json().as_bytes()).unwrap(), spec);
+	}
+
+	#[test]
+	fn mask_logits_forbids_illegal_moves() {
+		let masked = mask_logits(vec![5.0, 1.0, 2.0, 3.0], &[1.0, 0.0, 0.0, 1.0]);
+		assert_eq!(masked, vec![f32::NEG_INFINITY, 1.0, 2.0, f32::NEG_INFINITY]);
+		// DoNothing is never masked
+		assert_eq!(mask_logits(vec![1.0, 1.0, 1.0, 1.0, 0.0], &[1.0; 4])[4], 0.0);
+		// with no legal move at all the logits are kept as they are
+		assert_eq!(mask_logits(vec![1.0, 2.0, 3.0, 4.0], &[1.0; 4]), vec![1.0, 2.0, 3.0, 4.0]);
+	}
+
+	fn empty_engine() -> Engine {
+		Engine::new(EngineConfig {
+			board_size: 5,
+			populate_board: false,
+			random_seed: 123,
+			..EngineConfig::default()
+		})
+	}
+
+	#[test]
+	fn scripted_players_pick_their_targets() {
+		let mut engine = empty_engine();
+		engine.current_state.board.set_fruit(0, 1, Some(FruitType::Apple));
+		engine.current_state.board.set_fruit(3, 0, Some(FruitType::Banana));
+		engine.current_state.player2.position = Position::new(3, 2);
+		let state = &engine.current_state;
+
+		// the greedy player goes for the fruit closest to its own seat
+		let mut greedy = GreedyPlayer::new();
+		assert_eq!(greedy.get_move(state, PlayerIdx::Player1).unwrap(), Action::Move(Direction::Down));
+		assert_eq!(greedy.get_move(state, PlayerIdx::Player2).unwrap(), Action::Move(Direction::Up));
+		// the blocker goes for the fruit closest to the other seat
+		let mut blocker = BlockerPlayer::new();
+		assert_eq!(blocker.get_move(state, PlayerIdx::Player1).unwrap(), Action::Move(Direction::Right));
+		assert_eq!(blocker.get_move(state, PlayerIdx::Player2).unwrap(), Action::Move(Direction::Left));
+
+		// apples are already won by player 1, so only bananas are worth chasing
+		engine.current_state.player1.increment_fruit(FruitType::Apple, 2.0f32);
+		let mut contested = ContestedPlayer::new();
+		assert_eq!(contested.get_move(&engine.current_state, PlayerIdx::Player1).unwrap(), Action::Move(Direction::Right));
+		assert_eq!(contested.get_move(&engine.current_state, PlayerIdx::Player2).unwrap(), Action::Move(Direction::Up));
+	}
+
+	#[test]
+	fn scripted_opponents_by_name() {
+		for opponent in ScriptedOpponent::ALL.iter() {
+			assert_eq!(ScriptedOpponent::from_name(opponent.name()), Some(*opponent));
+		}
+		assert_eq!(ScriptedOpponent::from_name("minimax"), None);
+		assert_eq!(ScriptedOpponent::Random.agent_id(), 69420);
+	}
+
+	#[test]
+	fn mcts_takes_the_winning_fruit() {
+		let mut engine = empty_engine();
+		engine.current_state.board.set_fruit(1, 0, Some(FruitType::Apple));
+		engine.current_state.player2.position = Position::new(4, 4);
+		let conf = MctsConfig { playouts: 100, ..MctsConfig::default() };
+		assert!(MctsConfig { rollout_policy: ScriptedOpponent::Mcts, ..conf }.validate().is_err());
+
+		let mut mcts = MctsPlayer::new(engine.config.clone(), 50, conf);
+		let result = mcts.search(&engine.current_state).unwrap();
+		assert_eq!(result.best_action(PlayerIdx::Player1), Action::Move(Direction::Right));
+		assert_eq!(result.visits.0.iter().sum::<u32>(), 100);
+		assert_close(&[result.policy(PlayerIdx::Player2).iter().sum::<f32>()], &[1.0]);
+		// player 2 can't reach the apple in time, player 1 collects the win reward
+		assert!(result.values.0 > 0.0 && result.values.1 < 0.0);
+		// the seat it plays is the one it is asked about
+		assert_eq!(mcts.get_move(&engine.current_state, PlayerIdx::Player1).unwrap(), Action::Move(Direction::Right));
+	}
+
+	#[test]
+	fn mcts_uses_model_priors() {
+		let mut engine = empty_engine();
+		engine.current_state.board.set_fruit(1, 0, Some(FruitType::Apple));
+		engine.current_state.player2.position = Position::new(4, 4);
+		let encoder = EncoderKind::Flat { pad_to: None }.build();
+		let inputs = encoder.encode(&engine.current_state, PlayerIdx::Player1).len();
+		// a flat model: uniform priors and no value, so only the collected rewards steer the search
+		let bytes = safetensors_bytes(&[("0.weight", vec![5, inputs], vec![0.0; 5 * inputs]), ("0.bias", vec![5], vec![0.0; 5])]);
+		let model = Arc::new(Policy::new(Arc::new(MlpBackend::from_safetensors(&bytes).unwrap()), OutputSpec::default()));
+
+		let conf = MctsConfig { playouts: 50, prior_model: Some(3), ..MctsConfig::default() };
+		let mut mcts = MctsPlayer::new(engine.config.clone(), 50, conf).with_model(model, encoder);
+		assert!(mcts.check_model(&engine.current_state).is_ok());
+		let result = mcts.search(&engine.current_state).unwrap();
+		assert_eq!(result.best_action(PlayerIdx::Player1), Action::Move(Direction::Right));
+
+		// a model built for other observations is caught before the search
+		let model = Arc::new(Policy::new(Arc::new(MlpBackend::from_safetensors(&mlp_bytes()).unwrap()), OutputSpec::default()));
+		let mut mcts = MctsPlayer::new(engine.config.clone(), 50, conf).with_model(model, EncoderKind::Flat { pad_to: None }.build());
+		assert!(matches!(mcts.check_model(&engine.current_state), Err(SurgeError::Inference(3, _))));
+		// and a move from it fails instead of waiting a turn
+		assert!(matches!(mcts.get_move(&engine.current_state, PlayerIdx::Player1), Err(SurgeError::Inference(3, _))));
+	}
+
+	#[test]
+	fn matrix_games_solve_to_equilibrium() {
+		// matching pennies
+		let (value, rows, cols) = solve_matrix_game(&[vec![1.0, -1.0], vec![-1.0, 1.0]]);
+		assert_close(&[value], &[0.0]);
+		assert_close(&rows, &[0.5, 0.5]);
+		assert_close(&cols, &[0.5, 0.5]);
+		// the second row dominates and the column player answers it with the second column
+		let (value, rows, cols) = solve_matrix_game(&[vec![3.0, 1.0], vec![4.0, 2.0]]);
+		assert_close(&[value], &[2.0]);
+		assert_close(&rows, &[0.0, 1.0]);
+		assert_close(&cols, &[0.0, 1.0]);
+		// rock paper scissors with a doubled payoff for rock beating scissors
+		let (value, rows, cols) = solve_matrix_game(&[vec![0.0, -1.0, 2.0], vec![1.0, 0.0, -1.0], vec![-2.0, 1.0, 0.0]]);
+		assert_close(&[value], &[0.0]);
+		assert_close(&rows, &[0.25, 0.5, 0.25]);
+		assert_close(&cols, &[0.25, 0.5, 0.25]);
+	}
+
+	#[test]
+	fn solver_finds_contested_fruit() {
+		let mut engine = empty_engine();
+		engine.current_state.board.set_fruit(1, 0, Some(FruitType::Apple));
+		engine.current_state.player2.position = Position::new(2, 0);
+		let mut solver = Solver::new(engine.config.clone(), SolverConfig { max_rounds: 6, ..SolverConfig::default() });
+
+		// both players can reach the only fruit, so both take it and split it
+		let solution = solver.solve(&engine.current_state).unwrap();
+		assert_close(&[solution.value], &[0.0]);
+		assert!(solution.policy(PlayerIdx::Player1).contains(&(Action::Move(Direction::Right), 1.0)));
+		assert!(solution.policy(PlayerIdx::Player2).contains(&(Action::Move(Direction::Left), 1.0)));
+		assert!(solver.states_solved() > 1);
+		// wandering off lets the opponent take the fruit and the match
+		assert_close(&[solution.gap(PlayerIdx::Player1, &[0.0, 1.0, 0.0, 0.0])], &[1.0]);
+		assert_close(&[solution.gap(PlayerIdx::Player2, &[0.0, 0.0, 1.0, 0.0])], &[0.0]);
+
+		let mut player = SolverPlayer::new(solver);
+		assert_eq!(player.get_move(&engine.current_state, PlayerIdx::Player2).unwrap(), Action::Move(Direction::Left));
+
+		let mut small = Solver::new(engine.config.clone(), SolverConfig { max_rounds: 6, max_states: 1 });
+		engine.current_state.board.set_fruit(4, 4, Some(FruitType::Banana));
+		assert!(small.solve(&engine.current_state).is_err());
+		// a player over the bound fails up front, and its moves fail rather than wait
+		let mut player = SolverPlayer::new(small);
+		assert!(matches!(player.check_states(&engine.current_state), Err(SurgeError::InvalidConfig(_))));
+		assert!(player.get_move(&engine.current_state, PlayerIdx::Player1).is_err());
+	}
+
+	#[test]
+	fn elo_ratings_and_head_to_head() {
+		let mut table = RatingTable::new(RatingSystem::Elo { k_factor: 32.0 });
+		table.record((1, 2), WinState::Finished(PlayerWinner::Player1));
+		assert_eq!(table.rating(1).rating, 1516.0);
+		assert_eq!(table.rating(2).rating, 1484.0);
+		table.record((2, 1), WinState::Timeout);
+		table.record((3, 3), WinState::Finished(PlayerWinner::Player1));
+		assert_eq!(table.rating(3).matches, 0);
+
+		let record = table.head_to_head(2, 1);
+		assert_eq!((record.wins, record.losses, record.draws), (0, 1, 1));
+		assert!(record.expected_score < 0.5);
+		assert_eq!(table.opponents(1), vec![2]);
+		assert_eq!(table.ratings().iter().map(|(id, _)| *id).collect::<Vec<i32>>(), vec![1, 2]);
+	}
+
+	#[test]
+	fn trueskill_ratings_persist() {
+		let path = std::env::temp_dir().join(format!("surge-ratings-{}.json", std::process::id()));
+		let ratings = RatingStore::open(&path, RatingSystem::TrueSkill).unwrap();
+		let replay = MatchReplay::new(vec![], (5, 7), WinState::Finished(PlayerWinner::Player2));
+		ratings.record(&replay);
+		let winner = &ratings.ratings(&[7])[0].1;
+		let loser = &ratings.ratings(&[5])[0].1;
+		assert!(winner.rating > 25.0 && loser.rating < 25.0);
+		assert!(winner.deviation < 25.0 / 3.0 && loser.deviation < 25.0 / 3.0);
+		ratings.record(&MatchReplay::new(vec![], (5, 7), WinState::Draw));
+		ratings.save().unwrap();
+
+		let reopened = RatingStore::open(&path, RatingSystem::TrueSkill).unwrap();
+		assert_eq!(reopened.ratings(&[]), ratings.ratings(&[]));
+		assert_eq!(reopened.head_to_head(7, &[]), ratings.head_to_head(7, &[5]));
+		assert!(RatingStore::open(&path, RatingSystem::Elo { k_factor: 32.0 }).is_err());
+		std::fs::remove_file(path).unwrap();
+	}
+
+	#[test]
+	fn matchmaking_strategies() {
+		let mut pool = MatchmakingPool::new(1, vec![2, 4, 3]).with_strategy(MatchmakingKind::Latest.build());
+		assert_eq!(pool.sample_pair(), (1, 4));
+
+		// 1 keeps losing to 2 and beating 3, pfsp should mostly pick 2
+		let mut pool = MatchmakingPool::new(1, vec![2, 3])
+			.with_strategy(MatchmakingKind::from_name("pfsp").unwrap().build());
+		for _ in 0..10 {
+			pool.record(&MatchReplay::new(vec![], (1, 2), WinState::Finished(PlayerWinner::Player2)));
+			pool.record(&MatchReplay::new(vec![], (3, 1), WinState::Finished(PlayerWinner::Player2)));
+		}
+		assert!(PfspStrategy::loss_rate(1, 2, pool.stats()) > 0.9);
+		assert_eq!(PfspStrategy::loss_rate(1, 5, pool.stats()), 0.5);
+		let picked_2 = (0..200).filter(|_| pool.sample_opponent(MatchMode::VsPool) == Some(2)).count();
+		assert!(picked_2 > 180, "picked 2 {} times", picked_2);
+
+		// 2 and 3 are now far from 1 in opposite directions, 4 is unrated and within the band
+		let mut pool = MatchmakingPool::new(1, vec![2, 3, 4]).with_strategy(MatchmakingKind::EloBand { width: 100.0 }.build());
+		let mut stats = RatingTable::new(RatingSystem::Elo { k_factor: 32.0 });
+		for _ in 0..10 {
+			stats.record((1, 2), WinState::Finished(PlayerWinner::Player2));
+			stats.record((1, 3), WinState::Finished(PlayerWinner::Player1));
+		}
+		pool.seed(stats);
+		assert!((0..20).all(|_| pool.sample_pair() == (1, 4)));
+		assert!(MatchmakingKind::Pfsp { exponent: -1.0 }.validate().is_err());
+		assert!(MatchmakingKind::EloBand { width: 0.0 }.validate().is_err());
+		assert_eq!(MatchmakingKind::from_name("elo_band").unwrap().name(), "elo_band");
+
+		// band widths are Elo points, TrueSkill ratings are on another scale
+		let mut conf = rollout_config(MatchMode::VsPool, vec![2]);
+		conf.matchmaking = MatchmakingKind::EloBand { width: 200.0 };
+		let model_store = Arc::new(ModelStore::new(std::env::temp_dir().join("surge-unused"), 1, 0));
+		let ratings_path = std::env::temp_dir().join(format!("surge-band-{}.json", std::process::id()));
+		let trueskill = Arc::new(RatingStore::open(&ratings_path, RatingSystem::TrueSkill).unwrap());
+		let elo = Arc::new(RatingStore::open(&ratings_path, RatingSystem::Elo { k_factor: 32.0 }).unwrap());
+		assert!(RolloutManager::new(conf.clone(), model_store.clone(), 1).with_ratings(elo).validate().is_ok());
+		assert!(matches!(RolloutManager::new(conf, model_store, 1).with_ratings(trueskill).validate(), Err(SurgeError::InvalidConfig(_))));
+	}
+
+	#[test]
+	fn league_roles_and_snapshots() {
+		let conf = LeagueConfig { min_matches: 4, max_matches: 10, ..LeagueConfig::default() };
+		let mut league = League::new(conf);
+		league.add_agent(1, AgentRole::Main).unwrap();
+		league.add_agent(2, AgentRole::MainExploiter).unwrap();
+		league.add_agent(3, AgentRole::LeagueExploiter).unwrap();
+		assert!(league.add_agent(1, AgentRole::Main).is_err());
+		assert!(league.add_agent(conf.first_snapshot_id, AgentRole::Main).is_err());
+		assert_eq!(league.matchup(1).unwrap().opponent_ids, vec![1]);
+		assert_eq!(league.matchup(2).unwrap().opponent_ids, vec![1]);
+		assert_eq!(league.matchup(3).unwrap().opponent_ids, vec![1]);
+		assert_eq!(league.matchup(4), Err(SurgeError::LeagueAgentNotFound(4)));
+
+		// three wins and a draw out of four is past the 0.7 threshold, self-play doesn't count
+		let win = MatchReplay::new(vec![], (1, 2), WinState::Finished(PlayerWinner::Player1));
+		let loss_as_p2 = MatchReplay::new(vec![], (3, 1), WinState::Finished(PlayerWinner::Player1));
+		let self_play = MatchReplay::new(vec![], (1, 1), WinState::Finished(PlayerWinner::Player1));
+		league.record(1, &[win.clone(), win.clone(), self_play.clone()]).unwrap();
+		assert_eq!(league.agent(1).unwrap().matches, 2);
+		assert!(!league.snapshot_due(1).unwrap());
+		league.record(1, &[win.clone(), MatchReplay::new(vec![], (2, 1), WinState::Draw)]).unwrap();
+		assert!(league.snapshot_due(1).unwrap());
+		let snapshot = league.freeze(1).unwrap();
+		assert_eq!(snapshot.snapshot_id, conf.first_snapshot_id);
+		assert_eq!(league.agent(1).unwrap().matches, 0);
+		assert_eq!(league.matchup(1).unwrap().opponent_ids, vec![1, snapshot.snapshot_id]);
+		assert_eq!(league.matchup(3).unwrap().opponent_ids, vec![snapshot.snapshot_id]);
+
+		// losing agents are frozen once they reach max_matches
+		league.record(1, &vec![loss_as_p2; 10]).unwrap();
+		assert!(league.snapshot_due(1).unwrap());
+
+		let model_dir = std::env::temp_dir().join(format!("surge-league-{}", std::process::id()));
+		let league_path = model_dir.join("league.json");
+		let model_store = ModelStore::new(&model_dir, 4, 0);
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &mlp_bytes()).unwrap();
+		let store = LeagueStore::open(&league_path, conf).unwrap();
+		store.add_agent(1, AgentRole::Main).unwrap();
+		assert_eq!(store.record_rollout(1, &[win.clone(), win.clone()], &model_store).unwrap(), None);
+		let snapshot = store.record_rollout(1, &[win.clone(), win.clone()], &model_store).unwrap().unwrap();
+		assert!(model_store.get(snapshot.snapshot_id).is_ok());
+		store.save().unwrap();
+
+		let reopened = LeagueStore::open(&league_path, conf).unwrap();
+		assert_eq!(reopened.agents(), store.agents());
+		assert_eq!(reopened.snapshots(), vec![snapshot]);
+		assert_eq!(reopened.matchup(1).unwrap().opponent_ids, vec![1, snapshot.snapshot_id]);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+
+	#[test]
+	fn lone_main_agent_freezes_from_self_play() {
+		let conf = LeagueConfig { min_matches: 4, max_matches: 10, ..LeagueConfig::default() };
+		let model_dir = std::env::temp_dir().join(format!("surge-lone-main-{}", std::process::id()));
+		let model_store = ModelStore::new(&model_dir, 4, 0);
+		model_store.save_model(1, ModelFormat::SafeTensors, OutputSpec::default(), &mlp_bytes()).unwrap();
+		let store = LeagueStore::open(model_dir.join("league.json"), conf).unwrap();
+		store.add_agent(1, AgentRole::Main).unwrap();
+
+		// a main agent alone in the league only ever plays itself
+		let opponent_ids = store.matchup(1).unwrap().opponent_ids;
+		assert_eq!(opponent_ids, vec![1]);
+		let replays = (0..5)
+			.map(|idx| MatchReplay::new(vec![], (1, opponent_ids[0]), if idx % 2 == 0 { WinState::Draw } else { WinState::Finished(PlayerWinner::Player1) }))
+			.collect::<Vec<MatchReplay>>();
+		assert_eq!(store.record_rollout(1, &replays, &model_store).unwrap(), None);
+		assert_eq!((store.agents()[0].matches, store.agents()[0].played), (0, 5));
+		let snapshot = store.record_rollout(1, &replays, &model_store).unwrap().unwrap();
+		assert_eq!(store.matchup(1).unwrap().opponent_ids, vec![1, snapshot.snapshot_id]);
+		assert_eq!(store.agents()[0].played, 0);
+
+		// an agent due for a snapshot needs a model to copy
+		store.add_agent(2, AgentRole::Main).unwrap();
+		let replays = replays.iter().map(|replay| MatchReplay { agent_ids: (2, 2), ..replay.clone() }).collect::<Vec<MatchReplay>>();
+		store.record_rollout(2, &replays, &model_store).unwrap();
+		assert_eq!(store.record_rollout(2, &replays, &model_store).unwrap_err(), SurgeError::ModelNotFound(2));
+		assert_eq!(store.record_rollout(3, &replays, &model_store).unwrap_err(), SurgeError::LeagueAgentNotFound(3));
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
 }
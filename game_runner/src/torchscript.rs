@@ -0,0 +1,54 @@
+use std::path::Path;
+
+use tch::{CModule, IValue, Tensor};
+
+use crate::inference::{InferenceBackend, ModelOutputs};
+
+pub struct TorchScriptBackend {
+	module: CModule,
+}
+
+impl TorchScriptBackend {
+	pub fn load(path: &Path) -> Result<Self, String> {
+		let module = CModule::load(path).map_err(|err| err.to_string())?;
+		Ok(TorchScriptBackend {
+			module
+		})
+	}
+}
+
+impl InferenceBackend for TorchScriptBackend {
+	fn forward(self: &Self, states: &[Vec<f32>]) -> Result<ModelOutputs, String> {
+		if states.is_empty() {
+			return Ok(vec![]);
+		}
+		let batch_size = states.len() as i64;
+		let state_tensor = Tensor::of_slice(&states.concat())
+			.view(&[batch_size, states[0].len() as i64]);
+		let pred = self.module.forward_is(&[IValue::Tensor(state_tensor)]).map_err(|err| err.to_string())?;
+
+		// a module returning a tuple of tensors gives one output per element
+		let tensors = match pred {
+			IValue::Tensor(tensor) => vec![tensor],
+			IValue::Tuple(values) | IValue::GenericList(values) => values.into_iter()
+				.map(|value| match value {
+					IValue::Tensor(tensor) => Ok(tensor),
+					other => Err(format!("expected tensor outputs, got {:?}", other)),
+				})
+				.collect::<Result<Vec<Tensor>, String>>()?,
+			IValue::TensorList(tensors) => tensors,
+			other => return Err(format!("expected tensor outputs, got {:?}", other)),
+		};
+		tensors.iter()
+			.map(|tensor| {
+				let size = tensor.size();
+				if size.is_empty() || size[0] != batch_size {
+					return Err(format!("expected outputs with a leading batch dimension of {}, got {:?}", batch_size, size));
+				}
+				let values = Vec::<f32>::from(&tensor.contiguous().view(&[-1]));
+				let row_len = (values.len() / states.len()).max(1);
+				Ok(values.chunks(row_len).map(|row| row.to_vec()).collect())
+			})
+			.collect()
+	}
+}
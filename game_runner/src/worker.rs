@@ -1,197 +1,454 @@
 use std::collections::{HashMap, HashSet};
 use std::convert::TryInto;
 use std::iter::FromIterator;
-use std::path::Path;
-use std::time::Instant;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::Sender;
 
 use ndarray::Array;
-use rand::rngs::ThreadRng;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
+use rand::{Rng, SeedableRng, thread_rng};
+use rand::rngs::StdRng;
 use rand_distr::num_traits::Pow;
 use rand_distr::num_traits::real::Real;
-use tch::{CModule, Tensor, IndexOp};
-use tch::nn::Module;
 
-use engine::engine::Engine;
-use engine::state::{Action, Direction, FruitType, GameState, MatchReplay, PlayerIdx, PlayerWinner, SAR, WinState};
+use engine::engine::{Engine, EngineConfig};
+use engine::observation::ObservationEncoder;
+use engine::state::{Action, Direction, FruitType, GameState, MatchReplay, PlayerIdx, SAR, WinState};
 use engine::state::Direction::Up;
 
-use crate::ai::{AiPlayer, RandomPlayer};
+use crate::ai::AiPlayer;
+use crate::error::SurgeError;
+use crate::inference::Policy;
 use crate::manager::RolloutConfig;
+use crate::matchmaking::MatchmakingPool;
+use crate::mcts::MctsPlayer;
+use crate::model_store::ModelStore;
+use crate::ratings::RatingStore;
 use std::process::exit;
 
-pub struct RolloutWorker<'a> {
+pub struct RolloutWorker {
 	conf: RolloutConfig,
 	engine: Engine,
-	model_store: ModelStore<'a>,
+	model_store: Arc<ModelStore>,
+	// models used by the matches in flight, refreshed from the store whenever a match starts
+	models: HashMap<i32, Arc<Policy>>,
+	encoder: Box<dyn ObservationEncoder + Send>,
 	matchmaking: MatchmakingPool,
-	match_history: Vec<MatchReplay>
+	match_history: Vec<MatchReplay>,
+	matches_played: usize,
+	// finished matches go here instead of match_history when streaming
+	sink: Option<Sender<MatchReplay>>,
+	stop: Arc<AtomicBool>,
+	ratings: Option<Arc<RatingStore>>,
+	// draws the engine seed of every match from the worker's seed so no two matches share a board
+	seeds: StdRng,
 }
 
-pub struct ModelStore<'a> {
-	pub root_path: &'a Path,
-	pub model_ids: Vec<i32>,
-	pub models_hash: HashMap<i32, CModule>,
+struct MatchSlot {
+	engine: Engine,
+	agent_ids: (i32, i32),
+	opponent: Option<Box<dyn AiPlayer>>,
 }
 
-impl<'a> ModelStore<'a> {
-	pub fn new(path: &'a str, model_ids: Vec<i32>) -> Self {
-		let root_path = &Path::new(path);
-		let mut models_hash = HashMap::<i32, CModule>::new();
-		for id in &model_ids {
-			let current = format!("{}.pt", id.to_string().as_str());
-			let model_path = root_path.join(&Path::new(current.as_str()));
-			let model = ModelStore::load_model(model_path.to_str().unwrap());
-			models_hash.insert(*id, model);
-		}
-
-		ModelStore {
-			root_path,
-			model_ids,
-			models_hash,
-		}
-	}
+// what a model chose for one seat, and how it rated the position
+#[derive(Debug, Clone)]
+pub struct Prediction {
+	pub action: Action,
+	pub value: f32,
+	pub log_prob: f32,
+	// masked logits, only filled when RolloutConfig::record_logits is set
+	pub logits: Vec<f32>,
+}
 
-	fn load_model(model_path: &str) -> CModule {
-		// println!("{}", model_path);
-		let model = tch::CModule::load(model_path).unwrap_or_else(|_| {
-			panic!("fuck");
-		});
-		model
-	}
+#[derive(Default)]
+struct BatchRequest {
+	seats: Vec<(usize, PlayerIdx)>,
+	states: Vec<Vec<f32>>,
+	masks: Vec<Vec<f32>>,
 }
 
-impl<'a> RolloutWorker<'a> {
-	pub fn new(conf: RolloutConfig, model_store: ModelStore<'a>) -> Self {
+impl RolloutWorker {
+	pub fn new(conf: RolloutConfig, model_store: Arc<ModelStore>) -> Self {
 		let engine = Engine::new(conf.engine_config.clone());
+		let seeds = StdRng::seed_from_u64(conf.engine_config.random_seed);
 		RolloutWorker {
-			matchmaking: MatchmakingPool {
-				target_id: (&conf.agent_ids.0).clone(),
-				opponent_ids: (&conf.agent_ids.1).clone(),
-				rng: thread_rng(),
-			},
+			matchmaking: MatchmakingPool::new(conf.agent_ids.0, conf.agent_ids.1.clone())
+				.with_strategy(conf.matchmaking.build()),
+			encoder: conf.observation.build(),
 			conf,
 			engine,
 			model_store,
-			match_history: Vec::new()
+			models: HashMap::new(),
+			match_history: Vec::new(),
+			matches_played: 0,
+			sink: None,
+			stop: Arc::new(AtomicBool::new(false)),
+			ratings: None,
+			seeds,
+		}
+	}
+
+	// sends each replay to sink as soon as its match ends, the worker winds down once stop is set
+	pub fn with_stream(mut self, sink: Sender<MatchReplay>, stop: Arc<AtomicBool>) -> Self {
+		self.sink = Some(sink);
+		self.stop = stop;
+		self
+	}
+
+	// every finished match updates the ratings of both agents, matchmaking starts from the ratings so far
+	pub fn with_ratings(mut self, ratings: Arc<RatingStore>) -> Self {
+		self.matchmaking.seed(ratings.snapshot());
+		self.ratings = Some(ratings);
+		self
+	}
+
+	fn stopped(self: &Self) -> bool {
+		self.stop.load(Ordering::Relaxed)
+	}
+
+	fn finish_match(self: &mut Self, match_replay: MatchReplay) {
+		self.matches_played += 1;
+		self.matchmaking.record(&match_replay);
+		if let Some(ratings) = self.ratings.as_ref() {
+			ratings.record(&match_replay);
+		}
+		match self.sink.as_ref() {
+			Some(sink) => {
+				// the receiver is gone, nobody wants the rest of the run
+				if sink.send(match_replay).is_err() {
+					self.stop.store(true, Ordering::Relaxed);
+				}
+			}
+			None => self.match_history.push(match_replay),
 		}
 	}
 
-	pub fn play_match_agents(self: &mut Self) {
+	pub fn play_match_agents(self: &mut Self) -> Result<(), SurgeError> {
 		let agent_ids = self.matchmaking.sample_pair();
-		let start = Instant::now();
+		self.play_match_pair(agent_ids)
+	}
+
+	fn play_match_pair(self: &mut Self, agent_ids: (i32, i32)) -> Result<(), SurgeError> {
 		self.reset();
+		self.fetch_model(agent_ids.0)?;
+		self.fetch_model(agent_ids.1)?;
 		while self.engine.current_state.match_status == WinState::InProgress {
 			let states = (
-				GameState::get_state_vec_view(&self.engine.current_state, PlayerIdx::Player1),
-				GameState::get_state_vec_view(&self.engine.current_state, PlayerIdx::Player2)
+				self.observe(&self.engine.current_state, PlayerIdx::Player1),
+				self.observe(&self.engine.current_state, PlayerIdx::Player2)
 			);
-			let actions =
+			let preds =
 				(
-					self.run_model(agent_ids.0, states.0.0, states.0.1, self.conf.evaluation_mode),
-					self.run_model(agent_ids.1, states.1.0, states.1.1, self.conf.evaluation_mode)
+					self.run_model(agent_ids.0, states.0.0, states.0.1, self.conf.evaluation_mode)?,
+					self.run_model(agent_ids.1, states.1.0, states.1.1, self.conf.evaluation_mode)?
 				);
-			self.engine.apply_move((actions.0.0, actions.1.0), Some(-0.1f32));
+			self.engine.apply_move((preds.0.action, preds.1.action), None);
+			self.engine.record_values((preds.0.value, preds.1.value));
+			self.engine.record_log_probs((preds.0.log_prob, preds.1.log_prob));
+			self.engine.record_logits((preds.0.logits, preds.1.logits));
 
 			if self.engine.current_state.round >= self.conf.max_rounds as u32 {
+				self.engine.truncate();
 				break;
 			}
 		}
-		let winstate = self.engine.current_state.match_status;
-		println!("{:?}", winstate);
-		let duration = start.elapsed();
-
-		println!("Time elapsed in expensive_function() is: {:?}", duration);
-		// self.sar_store.append(&mut self.engine.game_history);
-		// self.win_history.push((agent_ids.0, agent_ids.1, winstate == WinState::Finished(PlayerWinner::Player1)))
+		let mut match_replay = MatchReplay::new(
+			self.engine.game_history.clone(), agent_ids, self.engine.current_state.match_status);
+		if match_replay.truncated() {
+			match_replay.bootstrap_values = self.bootstrap_values(&self.engine.current_state, agent_ids, false)?;
+		}
+		self.finish_match(match_replay);
+		Ok(())
 	}
 
-	pub fn play_match_ai(self: &mut Self) {
-		let player_id = self.matchmaking.target_id.clone();
-		let mut opponent = RandomPlayer::new();
+	pub fn play_match_ai(self: &mut Self) -> Result<(), SurgeError> {
+		let player_id = self.matchmaking.target_id();
+		let mut opponent = self.scripted_player()?;
 
 		self.reset();
+		self.fetch_model(player_id)?;
 		while self.engine.current_state.match_status == WinState::InProgress {
-			let state = GameState::get_state_vec_view(&self.engine.current_state, PlayerIdx::Player1);
-			let (player_action, player_value) = self.run_model(player_id.clone(), state.0, state.1, self.conf.evaluation_mode);
-			let opponent_action = opponent.get_move(&self.engine.current_state);
-			self.engine.apply_move((player_action, opponent_action), Some(-0.1f32));
+			let state = self.observe(&self.engine.current_state, PlayerIdx::Player1);
+			let pred = self.run_model(player_id.clone(), state.0, state.1, self.conf.evaluation_mode)?;
+			let opponent_action = opponent.get_move(&self.engine.current_state, PlayerIdx::Player2)?;
+			self.engine.apply_move((pred.action, opponent_action), None);
+			self.engine.record_values((pred.value, 0.0f32));
+			self.engine.record_log_probs((pred.log_prob, 0.0f32));
+			self.engine.record_logits((pred.logits, vec![]));
 
 			if self.engine.current_state.round >= self.conf.max_rounds as u32 {
+				self.engine.truncate();
 				break;
 			}
 		}
-		let winstate = self.engine.current_state.match_status;
-
-		let match_replay = MatchReplay {
-			sars: self.engine.game_history.clone(),
-			agent_ids: (player_id, 69420),
-			p1_won: winstate == WinState::Finished(PlayerWinner::Player1)
-		};
-		self.match_history.push(match_replay);
+		let agent_ids = (player_id, self.conf.scripted_opponent.agent_id());
+		let mut match_replay = MatchReplay::new(
+			self.engine.game_history.clone(), agent_ids, self.engine.current_state.match_status);
+		if match_replay.truncated() {
+			match_replay.bootstrap_values = self.bootstrap_values(&self.engine.current_state, agent_ids, true)?;
+		}
+		self.finish_match(match_replay);
 		// self.sar_store.append(&mut self.engine.game_history);
+		Ok(())
 	}
 
-	pub fn play_matches(self: &mut Self) -> Vec<MatchReplay> {
-		while self.match_history.len() < self.conf.max_matches as usize {
-			self.play_match_ai();
+	pub fn play_matches(self: &mut Self) -> Result<Vec<MatchReplay>, SurgeError> {
+		self.conf.validate()?;
+		self.conf.matchmaking.validate_stats(self.matchmaking.stats().system())?;
+		if self.conf.batch_size > 1 {
+			return self.play_matches_batched();
 		}
-		self.match_history.clone()
+		while self.matches_played < self.conf.max_matches as usize && !self.stopped() {
+			match self.matchmaking.sample_opponent(self.conf.match_mode) {
+				Some(opponent_id) => self.play_match_pair((self.matchmaking.target_id(), opponent_id))?,
+				None => self.play_match_ai()?,
+			}
+		}
+		Ok(self.match_history.clone())
 	}
 
-	pub fn run_model(self: &Self, model_idx: i32, state_vec: Vec<f32>, action_mask: Vec<f32>,
-					 evaluation_mode: bool) -> (Action, f32) {
-		let state_tensor = Tensor::of_slice(&state_vec);
-		let mut action_vector = Tensor::of_slice(&action_mask);
-		action_vector = action_vector * Tensor::from(-2.0f32);
-
-		let pred = self.model_store.models_hash.get(&model_idx).unwrap().forward(&state_tensor);
-		let mut action_pred = pred.i(0..4);
-		let value_pred = pred.i(4);
-
-		let action_pred = action_pred + action_vector;
-		let action_idx = if evaluation_mode {
-			i32::from(action_pred.argmax(0, false))
+	// steps up to batch_size engines in lockstep so every model runs one forward pass per step
+	pub fn play_matches_batched(self: &mut Self) -> Result<Vec<MatchReplay>, SurgeError> {
+		let max_matches = self.conf.max_matches as usize;
+		let batch_size = (self.conf.batch_size as usize).min(max_matches);
+		let mut slots: Vec<MatchSlot> = (0..batch_size)
+			.map(|_| self.new_slot())
+			.collect::<Result<Vec<MatchSlot>, SurgeError>>()?;
+		let mut matches_started = slots.len();
+
+		while !slots.is_empty() && !self.stopped() {
+			let mut requests: HashMap<i32, BatchRequest> = HashMap::new();
+			for (slot_idx, slot) in slots.iter().enumerate() {
+				let mut seats = vec![PlayerIdx::Player1];
+				if slot.opponent.is_none() {
+					seats.push(PlayerIdx::Player2);
+				}
+				for seat in seats {
+					let model_id = match seat {
+						PlayerIdx::Player1 => slot.agent_ids.0,
+						PlayerIdx::Player2 => slot.agent_ids.1,
+					};
+					let (state, mask) = self.observe(&slot.engine.current_state, seat);
+					let request = requests.entry(model_id).or_insert_with(BatchRequest::default);
+					request.seats.push((slot_idx, seat));
+					request.states.push(state);
+					request.masks.push(mask);
+				}
+			}
+
+			let mut actions = vec![(Action::DoNothing, Action::DoNothing); slots.len()];
+			let mut values = vec![(0.0f32, 0.0f32); slots.len()];
+			let mut log_probs = vec![(0.0f32, 0.0f32); slots.len()];
+			let mut logits: Vec<(Vec<f32>, Vec<f32>)> = vec![(vec![], vec![]); slots.len()];
+			for (model_id, request) in requests {
+				let preds = self.run_model_batch(model_id, &request.states, &request.masks, self.conf.evaluation_mode)?;
+				for ((slot_idx, seat), pred) in request.seats.into_iter().zip(preds) {
+					match seat {
+						PlayerIdx::Player1 => {
+							actions[slot_idx].0 = pred.action;
+							values[slot_idx].0 = pred.value;
+							log_probs[slot_idx].0 = pred.log_prob;
+							logits[slot_idx].0 = pred.logits;
+						}
+						PlayerIdx::Player2 => {
+							actions[slot_idx].1 = pred.action;
+							values[slot_idx].1 = pred.value;
+							log_probs[slot_idx].1 = pred.log_prob;
+							logits[slot_idx].1 = pred.logits;
+						}
+					}
+				}
+			}
+
+			let mut finished = vec![];
+			for (slot_idx, slot) in slots.iter_mut().enumerate() {
+				if let Some(opponent) = slot.opponent.as_mut() {
+					actions[slot_idx].1 = opponent.get_move(&slot.engine.current_state, PlayerIdx::Player2)?;
+				}
+				let mut winstate = slot.engine.apply_move(actions[slot_idx], None);
+				slot.engine.record_values(values[slot_idx]);
+				slot.engine.record_log_probs(log_probs[slot_idx]);
+				slot.engine.record_logits(std::mem::take(&mut logits[slot_idx]));
+				if winstate == WinState::InProgress && slot.engine.current_state.round >= self.conf.max_rounds {
+					winstate = slot.engine.truncate();
+				}
+				if winstate != WinState::InProgress {
+					finished.push(slot_idx);
+				}
+			}
+
+			// walk backwards so swap_remove leaves the remaining finished indices valid
+			for slot_idx in finished.into_iter().rev() {
+				let slot = if matches_started < max_matches && !self.stopped() {
+					matches_started += 1;
+					let new_slot = self.new_slot()?;
+					std::mem::replace(&mut slots[slot_idx], new_slot)
+				} else {
+					slots.swap_remove(slot_idx)
+				};
+				let outcome = slot.engine.current_state.match_status;
+				let mut match_replay = MatchReplay::new(slot.engine.game_history, slot.agent_ids, outcome);
+				if match_replay.truncated() {
+					match_replay.bootstrap_values =
+						self.bootstrap_values(&slot.engine.current_state, slot.agent_ids, slot.opponent.is_some())?;
+				}
+				self.finish_match(match_replay);
+			}
+			self.release_models(&slots);
+		}
+		Ok(self.match_history.clone())
+	}
+
+	fn new_slot(self: &mut Self) -> Result<MatchSlot, SurgeError> {
+		let engine_config = self.match_engine_config();
+		let target_id = self.matchmaking.target_id();
+		let (agent_ids, opponent): ((i32, i32), Option<Box<dyn AiPlayer>>) =
+			match self.matchmaking.sample_opponent(self.conf.match_mode) {
+				Some(opponent_id) => ((target_id, opponent_id), None),
+				None => ((target_id, self.conf.scripted_opponent.agent_id()), Some(self.scripted_player()?)),
+			};
+		self.fetch_model(agent_ids.0)?;
+		if opponent.is_none() {
+			self.fetch_model(agent_ids.1)?;
+		}
+		Ok(MatchSlot {
+			engine: Engine::new(engine_config),
+			agent_ids,
+			opponent,
+		})
+	}
+
+	fn scripted_player(self: &Self) -> Result<Box<dyn AiPlayer>, SurgeError> {
+		if let Some(player) = self.conf.scripted_opponent.player() {
+			return Ok(player);
+		}
+		let mut player = MctsPlayer::new(self.conf.engine_config.clone(), self.conf.max_rounds, self.conf.mcts);
+		if let Some(model_id) = self.conf.mcts.prior_model {
+			player = player.with_model(self.model_store.get(model_id)?, self.conf.observation.build());
+			player.check_model(&self.engine.current_state)?;
+		}
+		Ok(Box::new(player))
+	}
+
+	fn observe(self: &Self, state: &GameState, idx: PlayerIdx) -> (Vec<f32>, Vec<f32>) {
+		(self.encoder.encode(state, idx), GameState::get_action_mask_vec(state, idx))
+	}
+
+	// values of the state a truncated match stopped in, so advantages can bootstrap from it
+	fn bootstrap_values(self: &Self, state: &GameState, agent_ids: (i32, i32), scripted_opponent: bool) -> Result<(f32, f32), SurgeError> {
+		let (state1, mask1) = self.observe(state, PlayerIdx::Player1);
+		let value1 = self.run_model(agent_ids.0, state1, mask1, true)?.value;
+		let value2 = if scripted_opponent {
+			0.0f32
 		} else {
-			i32::from(action_pred.softmax(0, tch::Kind::Float).multinomial(1, true))
+			let (state2, mask2) = self.observe(state, PlayerIdx::Player2);
+			self.run_model(agent_ids.1, state2, mask2, true)?.value
 		};
+		Ok((value1, value2))
+	}
+
+	pub fn run_model(self: &Self, model_idx: i32, state_vec: Vec<f32>, action_mask: Vec<f32>,
+					 evaluation_mode: bool) -> Result<Prediction, SurgeError> {
+		let mut preds = self.run_model_batch(model_idx, &[state_vec], &[action_mask], evaluation_mode)?;
+		Ok(preds.remove(0))
+	}
+
+	pub fn run_model_batch(self: &Self, model_idx: i32, state_vecs: &[Vec<f32>], action_masks: &[Vec<f32>],
+						   evaluation_mode: bool) -> Result<Vec<Prediction>, SurgeError> {
+		let model = self.models.get(&model_idx).ok_or(SurgeError::ModelNotFound(model_idx))?;
+		let outputs = model.evaluate(state_vecs)
+			.map_err(|err| SurgeError::Inference(model_idx, err))?;
 
-		let action = match action_idx {
+		let mut rng = thread_rng();
+		let mut preds = Vec::with_capacity(outputs.len());
+		for ((logits, value), action_mask) in outputs.into_iter().zip(action_masks) {
+			let logits = mask_logits(logits, action_mask);
+			let log_policy = log_softmax(&logits);
+			let action_idx = if evaluation_mode {
+				argmax(&logits)
+			} else {
+				// sample by walking the cumulative probabilities
+				let mut threshold = rng.gen::<f32>();
+				log_policy.iter()
+					.position(|log_prob| {
+						threshold -= log_prob.exp();
+						threshold < 0.0f32
+					})
+					.unwrap_or_else(|| argmax(&logits))
+			};
+			preds.push(Prediction {
+				action: RolloutWorker::action_from_idx(action_idx as i64),
+				value,
+				log_prob: log_policy[action_idx],
+				logits: if self.conf.record_logits { logits } else { vec![] },
+			});
+		}
+		Ok(preds)
+	}
+
+	fn action_from_idx(action_idx: i64) -> Action {
+		match action_idx {
 			0 => Action::Move(Direction::Up),
 			1 => Action::Move(Direction::Down),
 			2 => Action::Move(Direction::Left),
 			3 => Action::Move(Direction::Right),
 			_ => Action::DoNothing,
-		};
-		(action, f32::from(value_pred))
+		}
+	}
+
+	// keeps only the models of the matches still being played
+	fn release_models(self: &mut Self, slots: &[MatchSlot]) {
+		self.models.retain(|model_idx, _| slots.iter()
+			.any(|slot| slot.agent_ids.0 == *model_idx || (slot.opponent.is_none() && slot.agent_ids.1 == *model_idx)));
+	}
+
+	// picks up a new version of the model if its file changed since it was cached
+	fn fetch_model(&mut self, model_idx: i32) -> Result<(), SurgeError> {
+		let model = self.model_store.get(model_idx)?;
+		self.models.insert(model_idx, model);
+		Ok(())
+	}
+
+	fn match_engine_config(self: &mut Self) -> EngineConfig {
+		let mut engine_config = self.conf.engine_config.clone();
+		engine_config.random_seed = self.seeds.gen::<u64>();
+		engine_config
 	}
 
 	pub fn reset(&mut self) {
-		self.engine = Engine::new(self.conf.engine_config.clone());
+		self.engine = Engine::new(self.match_engine_config());
+		// the last match's models are dropped so the store's cache limits bound what stays loaded
+		self.models.clear();
 		// get new matchmaking settings
 		// load new models
 	}
 }
 
-pub struct MatchmakingPool {
-	target_id: i32,
-	opponent_ids: Vec<i32>,
-	rng: ThreadRng,
+fn argmax(values: &[f32]) -> usize {
+	values.iter()
+		.enumerate()
+		.fold(0, |best, (idx, value)| if *value > values[best] { idx } else { best })
 }
 
-impl MatchmakingPool {
-	pub fn new(target: i32, opponents: Vec<i32>) -> Self {
-		let mut thread_rng = ThreadRng::default();
-		Self {
-			target_id: target,
-			opponent_ids: opponents,
-			rng: thread_rng,
-		}
+// illegal moves get -inf so they are never picked, DoNothing is always legal
+// and a player with no legal move at all keeps the unmasked logits
+pub fn mask_logits(logits: Vec<f32>, action_mask: &[f32]) -> Vec<f32> {
+	let masked = logits.iter()
+		.enumerate()
+		.map(|(idx, logit)| match action_mask.get(idx) {
+			Some(invalid) if *invalid > 0.0f32 => f32::NEG_INFINITY,
+			_ => *logit,
+		})
+		.collect::<Vec<f32>>();
+	if masked.iter().all(|logit| *logit == f32::NEG_INFINITY) {
+		logits
+	} else {
+		masked
 	}
+}
 
-	pub fn sample_pair(self: &mut Self) -> (i32, i32) {
-		let opponent = self.opponent_ids.choose(&mut self.rng).unwrap();
-		(self.target_id.clone(), opponent.clone())
-	}
+fn log_softmax(logits: &[f32]) -> Vec<f32> {
+	let max = logits.iter().cloned().fold(f32::NEG_INFINITY, f32::max);
+	let log_sum = logits.iter().map(|logit| (logit - max).exp()).sum::<f32>().ln() + max;
+	logits.iter().map(|logit| logit - log_sum).collect()
 }
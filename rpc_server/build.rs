@@ -24,6 +24,9 @@ use std::error::Error;
 // }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-	tonic_build::compile_protos("protos/start_match.proto")?;
+	// proto3 optional fields are still behind a flag in the protoc bundled with prost-build
+	tonic_build::configure()
+		.protoc_arg("--experimental_allow_proto3_optional")
+		.compile(&["protos/start_match.proto"], &["protos"])?;
 	Ok(())
 }
\ No newline at end of file
@@ -1,24 +1,66 @@
-use std::pin::Pin;
-use std::sync::Arc;
+use std::env;
+use std::io;
+use std::path::PathBuf;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc as std_mpsc;
 use tokio::sync::mpsc;
-use tonic::{transport::Server, Request, Response, Status};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{transport::Server, Request, Response, Status, Streaming};
 use start_match::match_runner_server::{MatchRunner, MatchRunnerServer};
-use start_match::{RunnerConfig, MatchesResponse, Sar as SarView, MatchReplay as MatchReplayView, Action as ActionView};
-use engine::engine::{Engine, EngineConfig, SAR};
-use game_runner::worker::{ModelStore, RolloutWorker};
-use game_runner::manager::RolloutConfig;
-use tokio::macros::support::thread_rng_n;
+use start_match::{RunnerConfig, MatchesResponse, MatchUpdate, JobRequest, JobResults, JobStatus as JobStatusView, JobState as JobStateView, Sar as SarView, MatchReplay as MatchReplayView, MatchMode as MatchModeView, MatchOutcome as MatchOutcomeView,
+				  ModelChunk, ModelInfo, ModelFormat as ModelFormatView, OutputSpec as OutputSpecView, OutputLayout as OutputLayoutView, EngineSettings, MctsSettings, MatchmakingSettings, ObservationShape as ObservationShapeView, ObservationKind as ObservationKindView, ListModelsRequest, ListModelsResponse, DeleteModelRequest, DeleteModelResponse,
+				  RatingsRequest, RatingsResponse, AgentRating, HeadToHeadRequest, HeadToHeadResponse, HeadToHead as HeadToHeadView,
+				  AgentRole as AgentRoleView, LeagueAgentRequest, LeagueAgent as LeagueAgentView, LeagueSnapshot, LeagueRequest, LeagueResponse, LeagueMatchRequest, LeagueMatchResponse};
+use engine::engine::{EngineConfig, SAR};
+use game_runner::manager::{MatchMode, RolloutConfig, RolloutManager};
+use game_runner::ai::ScriptedOpponent;
+use game_runner::matchmaking::MatchmakingKind;
+use game_runner::mcts::MctsConfig;
+use game_runner::advantage::{replay_advantages, AdvantageConfig};
+use game_runner::error::SurgeError;
+use game_runner::inference::{ModelFormat, OutputLayout, OutputSpec};
+use game_runner::jobs::{JobId, JobQueue, JobState, JobStatus};
+use game_runner::league::{AgentRole, LeagueAgent, LeagueConfig, LeagueStore, Snapshot};
+use game_runner::model_store::ModelStore;
+use game_runner::ratings::{RatingStore, RatingSystem};
 use rand::{Rng, thread_rng};
 use std::convert::TryInto;
-use engine::state::{GameState, Action};
-use engine::state::PlayerIdx::Player1;
+use engine::state::{GameState, Action, MatchReplay, PlayerWinner, WinState};
+use engine::state::PlayerIdx;
+use engine::observation::{EncoderKind, ObservationEncoder};
 
 pub mod start_match {
 	tonic::include_proto!("surge_proto");
 }
 
-#[derive(Debug, Default)]
-pub struct MyMatchRunner {}
+pub struct MyMatchRunner {
+	model_store: Arc<ModelStore>,
+	ratings: Arc<RatingStore>,
+	league: Arc<LeagueStore>,
+	jobs: JobQueue,
+	// what each submitted job needs to encode its replays once they are fetched
+	job_views: Mutex<HashMap<JobId, JobView>>,
+}
+
+struct JobView {
+	observation: EncoderKind,
+	board_size: i8,
+	advantage_config: Option<AdvantageConfig>,
+}
+
+impl MyMatchRunner {
+	pub fn new(model_store: Arc<ModelStore>, ratings: Arc<RatingStore>, league: Arc<LeagueStore>, jobs: JobQueue) -> Self {
+		MyMatchRunner {
+			model_store,
+			ratings,
+			league,
+			jobs,
+			job_views: Mutex::new(HashMap::new()),
+		}
+	}
+}
 
 #[tonic::async_trait]
 impl MatchRunner for MyMatchRunner {
@@ -30,48 +72,23 @@ impl MatchRunner for MyMatchRunner {
 		}
 		println!("Got a config: {:?}", config);
 
-		let engine = Engine::new(EngineConfig::default());
-
-		let model_store = ModelStore::new(&"A:\\surge\\model_store\\fc_model",
-										  vec![1]);
-		let mut worker = RolloutWorker::new(RolloutConfig {
-			engine_config: EngineConfig {
-				random_seed: thread_rng().gen::<u64>(),
-				fruit_density: 0.2f32,
-				populate_board: true,
-				board_size: 10,
-			},
-			agent_ids: (config.target_id.try_into().unwrap(), config.agent_ids.try_into().unwrap()),
-			max_rounds: config.max_rounds.try_into().unwrap(),
-			evaluation_mode: config.evaluation_mode,
-			max_matches: config.max_matches.try_into().unwrap(),
-		}, model_store);
-		println!("Starting run until we have {} matches", config.max_matches.clone());
-		let res = worker.play_matches();
+		let num_workers = num_workers(&config);
+		let rollout_config = rollout_config(&config).map_err(surge_status)?;
+		let advantage_config = advantage_config(&config).map_err(surge_status)?;
+		let encoder = rollout_config.observation.build();
+		let observation_shape = observation_shape_view(&rollout_config.observation, rollout_config.engine_config.board_size);
+		let manager = RolloutManager::new(rollout_config, self.model_store.clone(), num_workers)
+			.with_ratings(self.ratings.clone());
+		println!("Starting run until we have {} matches on {} workers", config.max_matches.clone(), num_workers);
+		// rollouts block on inference, keep them off the async runtime
+		let res = tokio::task::spawn_blocking(move || manager.play_matches()).await
+			.map_err(|err| Status::internal(format!("rollout task failed: {}", err)))?
+			.map_err(surge_status)?;
 		// println!("Results: {:?}", res);
 
 		let mut view_match_res = vec![];
 		for replay in res {
-			let mut sars_view = vec![];
-			for sar in replay.sars {
-				let (gamestate, action_mask) = GameState::get_state_vec_view(&sar.gamestate, Player1);
-
-				let sar_view = SarView {
-					state: gamestate,
-					reward: sar.reward,
-					action: Action::as_num(sar.actions),
-					action_mask: sar.action_mask.iter().map(|a| Action::as_num(*a)).collect(),
-					terminal: sar.terminal
-				};
-				sars_view.push(sar_view);
-			}
-			let match_replay_view = MatchReplayView {
-				sars: sars_view,
-				player1_id: replay.agent_ids.0,
-				player2_id: replay.agent_ids.1,
-				result: replay.p1_won
-			};
-			view_match_res.push(match_replay_view);
+			view_match_res.push(replay_view(&replay, encoder.as_ref(), advantage_config.as_ref()));
 		}
 
 		// let sars = vec![Sar {
@@ -90,17 +107,700 @@ impl MatchRunner for MyMatchRunner {
 		// };
 
 		let reply = MatchesResponse {
-			replays: view_match_res
+			replays: view_match_res,
+			observation_shape: Some(observation_shape),
 		};
 
 		Ok(Response::new(reply)) // Send back our formatted greeting
 	}
+
+	type StreamMatchesStream = ReceiverStream<Result<MatchUpdate, Status>>;
+
+	async fn stream_matches(&self, request: Request<RunnerConfig>)
+							-> Result<Response<Self::StreamMatchesStream>, Status> {
+		let config: RunnerConfig = request.into_inner();
+		let num_workers = num_workers(&config);
+		let rollout_config = rollout_config(&config).map_err(surge_status)?;
+		let advantage_config = advantage_config(&config).map_err(surge_status)?;
+		let encoder = rollout_config.observation.build();
+		let mut observation_shape = Some(observation_shape_view(&rollout_config.observation, rollout_config.engine_config.board_size));
+		let stream_batch_size = config.stream_batch_size.max(1) as usize;
+		let total = config.max_matches;
+		let manager = RolloutManager::new(rollout_config, self.model_store.clone(), num_workers)
+			.with_ratings(self.ratings.clone());
+
+		let (tx, rx) = mpsc::channel(4);
+		tokio::task::spawn_blocking(move || {
+			let stop = Arc::new(AtomicBool::new(false));
+			let (replay_tx, replay_rx) = std_mpsc::channel();
+			let rollout_stop = stop.clone();
+			let rollout = std::thread::spawn(move || manager.stream_matches(replay_tx, rollout_stop));
+
+			let mut completed = 0;
+			let mut pending = vec![];
+			for replay in replay_rx.iter() {
+				completed += 1;
+				pending.push(replay_view(&replay, encoder.as_ref(), advantage_config.as_ref()));
+				if pending.len() < stream_batch_size {
+					continue;
+				}
+				let update = MatchUpdate {
+					replays: std::mem::take(&mut pending),
+					completed,
+					total,
+					observation_shape: observation_shape.take(),
+				};
+				if tx.blocking_send(Ok(update)).is_err() {
+					// the client cancelled, stop the workers instead of finishing the run
+					stop.store(true, Ordering::Relaxed);
+					break;
+				}
+			}
+			drop(replay_rx);
+
+			let last_update = match rollout.join() {
+				Ok(Ok(())) if !pending.is_empty() || observation_shape.is_some() => Ok(MatchUpdate {
+					replays: pending,
+					completed,
+					total,
+					observation_shape: observation_shape.take(),
+				}),
+				Ok(Ok(())) => return,
+				Ok(Err(err)) => Err(surge_status(err)),
+				Err(_) => Err(Status::internal("rollout thread panicked")),
+			};
+			let _ = tx.blocking_send(last_update);
+		});
+
+		Ok(Response::new(ReceiverStream::new(rx)))
+	}
+
+	async fn submit_job(&self, request: Request<RunnerConfig>)
+						-> Result<Response<JobStatusView>, Status> {
+		let config: RunnerConfig = request.into_inner();
+		let rollout_config = rollout_config(&config).map_err(surge_status)?;
+		let job_view = JobView {
+			observation: rollout_config.observation,
+			board_size: rollout_config.engine_config.board_size,
+			advantage_config: advantage_config(&config).map_err(surge_status)?,
+		};
+		let manager = RolloutManager::new(rollout_config, self.model_store.clone(), num_workers(&config))
+			.with_ratings(self.ratings.clone());
+		// held across submit so a runner that finishes instantly cannot race the view being stored
+		let mut job_views = self.job_views.lock().unwrap();
+		let status = self.jobs.submit(manager).map_err(surge_status)?;
+		job_views.insert(status.job_id, job_view);
+
+		Ok(Response::new(job_status_view(&status)))
+	}
+
+	async fn get_job_status(&self, request: Request<JobRequest>)
+							-> Result<Response<JobStatusView>, Status> {
+		let status = self.jobs.status(request.into_inner().job_id).map_err(surge_status)?;
+		Ok(Response::new(job_status_view(&status)))
+	}
+
+	async fn fetch_results(&self, request: Request<JobRequest>)
+						   -> Result<Response<JobResults>, Status> {
+		let job_id = request.into_inner().job_id;
+		let (status, replays) = self.jobs.fetch_results(job_id).map_err(surge_status)?;
+		let mut job_views = self.job_views.lock().unwrap();
+		let job_view = job_views.get(&job_id)
+			.ok_or_else(|| surge_status(SurgeError::JobNotFound(job_id)))?;
+		let encoder = job_view.observation.build();
+		let reply = JobResults {
+			status: Some(job_status_view(&status)),
+			replays: replays.iter()
+				.map(|replay| replay_view(replay, encoder.as_ref(), job_view.advantage_config.as_ref()))
+				.collect(),
+			observation_shape: Some(observation_shape_view(&job_view.observation, job_view.board_size)),
+		};
+		if status.finished() {
+			job_views.remove(&job_id);
+		}
+
+		Ok(Response::new(reply))
+	}
+
+	async fn cancel_job(&self, request: Request<JobRequest>)
+						-> Result<Response<JobStatusView>, Status> {
+		let status = self.jobs.cancel(request.into_inner().job_id).map_err(surge_status)?;
+		Ok(Response::new(job_status_view(&status)))
+	}
+
+	async fn upload_model(&self, request: Request<Streaming<ModelChunk>>)
+						  -> Result<Response<ModelInfo>, Status> {
+		let mut chunks = request.into_inner();
+		let mut agent_id = None;
+		let mut format = None;
+		let mut output_spec = OutputSpec::default();
+		let mut model_bytes = vec![];
+		while let Some(chunk) = chunks.message().await? {
+			match agent_id {
+				None => {
+					agent_id = Some(chunk.agent_id);
+					if let Some(spec) = chunk.output_spec.as_ref() {
+						output_spec = output_spec_from_view(spec)?;
+					}
+				}
+				Some(id) if id != chunk.agent_id => {
+					return Err(Status::invalid_argument(
+						format!("upload mixes chunks for agents {} and {}", id, chunk.agent_id)));
+				}
+				_ => {}
+			}
+			match format {
+				None => format = Some(chunk.format),
+				Some(format) if format != chunk.format => {
+					return Err(Status::invalid_argument("upload mixes chunks of different model formats"));
+				}
+				_ => {}
+			}
+			model_bytes.extend_from_slice(&chunk.data);
+		}
+		let agent_id = agent_id.ok_or_else(|| Status::invalid_argument("upload contained no chunks"))?;
+		let format = match format.and_then(ModelFormatView::from_i32) {
+			Some(ModelFormatView::AutoDetect) => ModelFormat::detect(&model_bytes)
+				.ok_or_else(|| Status::invalid_argument("could not detect the model format, upload it with an explicit format"))?,
+			Some(format) => model_format(format),
+			None => return Err(Status::invalid_argument("unknown model format")),
+		};
+		let size_bytes = model_bytes.len() as u64;
+
+		let model_store = self.model_store.clone();
+		tokio::task::spawn_blocking(move || model_store.save_model(agent_id, format, output_spec, &model_bytes))
+			.await
+			.map_err(|err| Status::internal(err.to_string()))?
+			.map_err(io_status)?;
+		println!("Stored model for agent {} ({} bytes)", agent_id, size_bytes);
+
+		Ok(Response::new(ModelInfo {
+			agent_id,
+			size_bytes,
+			format: model_format_view(format) as i32,
+			output_spec: Some(output_spec_view(&output_spec)),
+		}))
+	}
+
+	async fn list_models(&self, _request: Request<ListModelsRequest>)
+						 -> Result<Response<ListModelsResponse>, Status> {
+		let models = self.model_store.list_models()
+			.map_err(io_status)?
+			.into_iter()
+			.map(|(agent_id, format, size_bytes)| Ok(ModelInfo {
+				agent_id,
+				size_bytes,
+				format: model_format_view(format) as i32,
+				output_spec: Some(output_spec_view(&self.model_store.output_spec(agent_id).map_err(surge_status)?)),
+			}))
+			.collect::<Result<Vec<ModelInfo>, Status>>()?;
+
+		Ok(Response::new(ListModelsResponse {
+			models
+		}))
+	}
+
+	async fn delete_model(&self, request: Request<DeleteModelRequest>)
+						  -> Result<Response<DeleteModelResponse>, Status> {
+		let agent_id = request.into_inner().agent_id;
+		let deleted = self.model_store.delete_model(agent_id).map_err(io_status)?;
+
+		Ok(Response::new(DeleteModelResponse {
+			deleted
+		}))
+	}
+
+	async fn get_ratings(&self, request: Request<RatingsRequest>)
+						 -> Result<Response<RatingsResponse>, Status> {
+		let request = request.into_inner();
+		let ratings = self.ratings.ratings(&request.agent_ids)
+			.into_iter()
+			.map(|(agent_id, rating)| AgentRating {
+				agent_id,
+				rating: rating.rating,
+				deviation: rating.deviation,
+				matches: rating.matches,
+				wins: rating.wins,
+				losses: rating.losses,
+				draws: rating.draws,
+			})
+			.collect();
+
+		Ok(Response::new(RatingsResponse {
+			system: self.ratings.system().name().to_string(),
+			ratings
+		}))
+	}
+
+	async fn get_head_to_head(&self, request: Request<HeadToHeadRequest>)
+							  -> Result<Response<HeadToHeadResponse>, Status> {
+		let request = request.into_inner();
+		let records = self.ratings.head_to_head(request.agent_id, &request.opponent_ids)
+			.into_iter()
+			.map(|record| HeadToHeadView {
+				agent_id: record.agent_id,
+				opponent_id: record.opponent_id,
+				wins: record.wins,
+				losses: record.losses,
+				draws: record.draws,
+				expected_score: record.expected_score,
+			})
+			.collect();
+
+		Ok(Response::new(HeadToHeadResponse {
+			records
+		}))
+	}
+
+	async fn add_league_agent(&self, request: Request<LeagueAgentRequest>)
+							  -> Result<Response<LeagueAgentView>, Status> {
+		let request = request.into_inner();
+		let role = agent_role(request.role)?;
+		let agent = self.league.add_agent(request.agent_id, role).map_err(surge_status)?;
+		self.league.save().map_err(io_status)?;
+		println!("Added league agent {} as {}", agent.agent_id, role.name());
+
+		Ok(Response::new(league_agent_view(&agent)))
+	}
+
+	async fn remove_league_agent(&self, request: Request<LeagueAgentRequest>)
+								 -> Result<Response<LeagueAgentView>, Status> {
+		let agent = self.league.remove_agent(request.into_inner().agent_id).map_err(surge_status)?;
+		self.league.save().map_err(io_status)?;
+
+		Ok(Response::new(league_agent_view(&agent)))
+	}
+
+	async fn get_league(&self, _request: Request<LeagueRequest>)
+						-> Result<Response<LeagueResponse>, Status> {
+		Ok(Response::new(LeagueResponse {
+			agents: self.league.agents().iter().map(league_agent_view).collect(),
+			snapshots: self.league.snapshots().iter().map(league_snapshot_view).collect(),
+		}))
+	}
+
+	async fn start_league_match(&self, request: Request<LeagueMatchRequest>)
+								-> Result<Response<LeagueMatchResponse>, Status> {
+		let request = request.into_inner();
+		let agent_id = request.agent_id;
+		let matchup = self.league.matchup(agent_id).map_err(surge_status)?;
+		let mut config = request.config.unwrap_or_default();
+		config.target_id = agent_id;
+		config.agent_ids = matchup.opponent_ids.clone();
+		config.match_mode = MatchModeView::VsPool as i32;
+		config.matchmaking = None;
+
+		let num_workers = num_workers(&config);
+		let mut rollout_config = rollout_config(&config).map_err(surge_status)?;
+		rollout_config.matchmaking = matchup.matchmaking;
+		rollout_config.validate().map_err(surge_status)?;
+		let advantage_config = advantage_config(&config).map_err(surge_status)?;
+		let encoder = rollout_config.observation.build();
+		let observation_shape = observation_shape_view(&rollout_config.observation, rollout_config.engine_config.board_size);
+		let manager = RolloutManager::new(rollout_config, self.model_store.clone(), num_workers)
+			.with_ratings(self.ratings.clone());
+		println!("Starting league run for agent {} against {:?}", agent_id, matchup.opponent_ids);
+
+		let league = self.league.clone();
+		let model_store = self.model_store.clone();
+		let (replays, snapshot) = tokio::task::spawn_blocking(move || -> Result<_, Status> {
+			let replays = manager.play_matches().map_err(surge_status)?;
+			let snapshot = league.record_rollout(agent_id, &replays, &model_store).map_err(surge_status)?;
+			league.save().map_err(io_status)?;
+			Ok((replays, snapshot))
+		}).await
+			.map_err(|err| Status::internal(format!("rollout task failed: {}", err)))??;
+		if let Some(snapshot) = snapshot.as_ref() {
+			println!("Froze agent {} as snapshot {}", agent_id, snapshot.snapshot_id);
+		}
+
+		Ok(Response::new(LeagueMatchResponse {
+			replays: replays.iter()
+				.map(|replay| replay_view(replay, encoder.as_ref(), advantage_config.as_ref()))
+				.collect(),
+			observation_shape: Some(observation_shape),
+			opponent_ids: matchup.opponent_ids,
+			snapshot: snapshot.as_ref().map(league_snapshot_view),
+		}))
+	}
+}
+
+fn agent_role(role: i32) -> Result<AgentRole, Status> {
+	match AgentRoleView::from_i32(role) {
+		Some(AgentRoleView::Main) => Ok(AgentRole::Main),
+		Some(AgentRoleView::MainExploiter) => Ok(AgentRole::MainExploiter),
+		Some(AgentRoleView::LeagueExploiter) => Ok(AgentRole::LeagueExploiter),
+		None => Err(Status::invalid_argument(format!("unknown agent role {}", role))),
+	}
+}
+
+fn agent_role_view(role: AgentRole) -> AgentRoleView {
+	match role {
+		AgentRole::Main => AgentRoleView::Main,
+		AgentRole::MainExploiter => AgentRoleView::MainExploiter,
+		AgentRole::LeagueExploiter => AgentRoleView::LeagueExploiter,
+	}
+}
+
+fn league_agent_view(agent: &LeagueAgent) -> LeagueAgentView {
+	LeagueAgentView {
+		agent_id: agent.agent_id,
+		role: agent_role_view(agent.role) as i32,
+		snapshot_ids: agent.snapshots.clone(),
+		matches: agent.matches,
+		win_rate: agent.win_rate(),
+		played: agent.played,
+	}
+}
+
+fn league_snapshot_view(snapshot: &Snapshot) -> LeagueSnapshot {
+	LeagueSnapshot {
+		snapshot_id: snapshot.snapshot_id,
+		agent_id: snapshot.agent_id,
+		role: agent_role_view(snapshot.role) as i32,
+	}
+}
+
+// AutoDetect is resolved from the uploaded bytes before this
+fn model_format(format: ModelFormatView) -> ModelFormat {
+	match format {
+		ModelFormatView::TorchScript | ModelFormatView::AutoDetect => ModelFormat::TorchScript,
+		ModelFormatView::SafeTensors => ModelFormat::SafeTensors,
+		ModelFormatView::Onnx => ModelFormat::Onnx,
+	}
+}
+
+fn model_format_view(format: ModelFormat) -> ModelFormatView {
+	match format {
+		ModelFormat::TorchScript => ModelFormatView::TorchScript,
+		ModelFormat::SafeTensors => ModelFormatView::SafeTensors,
+		ModelFormat::Onnx => ModelFormatView::Onnx,
+	}
+}
+
+fn output_spec_from_view(spec: &OutputSpecView) -> Result<OutputSpec, Status> {
+	let layout = match OutputLayoutView::from_i32(spec.layout) {
+		Some(OutputLayoutView::Concatenated) => OutputLayout::Concatenated,
+		Some(OutputLayoutView::Tuple) => OutputLayout::Tuple,
+		None => return Err(Status::invalid_argument("unknown output layout")),
+	};
+	let output_spec = OutputSpec {
+		layout,
+		num_logits: spec.num_logits as usize,
+		value_head: spec.value_head,
+	};
+	output_spec.validate().map_err(Status::invalid_argument)?;
+	Ok(output_spec)
+}
+
+fn output_spec_view(spec: &OutputSpec) -> OutputSpecView {
+	let layout = match spec.layout {
+		OutputLayout::Concatenated => OutputLayoutView::Concatenated,
+		OutputLayout::Tuple => OutputLayoutView::Tuple,
+	};
+	OutputSpecView {
+		layout: layout as i32,
+		num_logits: spec.num_logits as u32,
+		value_head: spec.value_head,
+	}
+}
+
+fn num_workers(config: &RunnerConfig) -> usize {
+	if config.num_workers > 0 {
+		config.num_workers as usize
+	} else {
+		std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+	}
+}
+
+fn job_status_view(status: &JobStatus) -> JobStatusView {
+	let state = match status.state {
+		JobState::Queued => JobStateView::Queued,
+		JobState::Running => JobStateView::Running,
+		JobState::Completed => JobStateView::Completed,
+		JobState::Failed => JobStateView::Failed,
+		JobState::Cancelled => JobStateView::Cancelled,
+	};
+	JobStatusView {
+		job_id: status.job_id,
+		state: state as i32,
+		completed: status.completed as i32,
+		total: status.total as i32,
+		error: status.error.as_ref().map(|err| err.to_string()).unwrap_or_default(),
+	}
+}
+
+fn rollout_config(config: &RunnerConfig) -> Result<RolloutConfig, SurgeError> {
+	let non_negative = |value: i32, name: &str| -> Result<u32, SurgeError> {
+		value.try_into().map_err(|_| SurgeError::InvalidConfig(format!("{} must not be negative, got {}", name, value)))
+	};
+
+	let rollout_config = RolloutConfig {
+		engine_config: engine_config(config.engine.as_ref())?,
+		agent_ids: (config.target_id, config.agent_ids.clone()),
+		max_rounds: non_negative(config.max_rounds, "max_rounds")?,
+		evaluation_mode: config.evaluation_mode,
+		max_matches: non_negative(config.max_matches, "max_matches")?,
+		batch_size: non_negative(config.batch_size, "batch_size")?.max(1),
+		observation: encoder_kind(config)?,
+		record_logits: config.record_logits,
+		match_mode: match MatchModeView::from_i32(config.match_mode) {
+			Some(MatchModeView::VsRandom) => MatchMode::VsRandom,
+			Some(MatchModeView::VsPool) => MatchMode::VsPool,
+			Some(MatchModeView::Mixed) => MatchMode::Mixed(config.pool_ratio),
+			None => return Err(SurgeError::InvalidConfig(format!("unknown match_mode {}", config.match_mode))),
+		},
+		matchmaking: matchmaking_kind(config.matchmaking.as_ref())?,
+		scripted_opponent: scripted_opponent(&config.scripted_opponent, ScriptedOpponent::Random)?,
+		mcts: mcts_config(config.mcts.as_ref())?,
+	};
+	rollout_config.validate()?;
+	Ok(rollout_config)
+}
+
+fn advantage_config(config: &RunnerConfig) -> Result<Option<AdvantageConfig>, SurgeError> {
+	match config.advantage.as_ref() {
+		Some(settings) => {
+			let advantage_config = AdvantageConfig {
+				gamma: settings.gamma,
+				lambda: settings.lambda,
+			};
+			advantage_config.validate()?;
+			Ok(Some(advantage_config))
+		}
+		None => Ok(None),
+	}
+}
+
+fn encoder_kind(config: &RunnerConfig) -> Result<EncoderKind, SurgeError> {
+	let pad_to = match config.observation_padding {
+		0 => None,
+		pad_to => Some(pad_to.try_into()
+			.map_err(|_| SurgeError::InvalidConfig(format!("observation_padding {} is out of range", pad_to)))?),
+	};
+	match ObservationKindView::from_i32(config.observation_kind) {
+		Some(ObservationKindView::Flat) => Ok(EncoderKind::Flat { pad_to }),
+		Some(ObservationKindView::Spatial) => Ok(EncoderKind::Spatial { pad_to }),
+		Some(ObservationKindView::Egocentric) => {
+			let radius = config.egocentric_radius.try_into()
+				.map_err(|_| SurgeError::InvalidConfig(format!("egocentric_radius {} is out of range", config.egocentric_radius)))?;
+			Ok(EncoderKind::Egocentric { radius })
+		}
+		Some(ObservationKindView::Symbolic) => Ok(EncoderKind::Symbolic),
+		None => Err(SurgeError::InvalidConfig(format!("unknown observation_kind {}", config.observation_kind))),
+	}
+}
+
+fn observation_shape_view(kind: &EncoderKind, board_size: i8) -> ObservationShapeView {
+	let dims = kind.build().shape(board_size);
+	let mut view = ObservationShapeView {
+		length: dims.iter().product::<usize>() as i32,
+		dims: dims.iter().map(|dim| *dim as i32).collect(),
+		..ObservationShapeView::default()
+	};
+	if let EncoderKind::Flat { pad_to } = kind {
+		let flat_shape = GameState::observation_shape(board_size, *pad_to);
+		view.map_size = flat_shape.map_size as i32;
+		view.map_channels = flat_shape.map_channels as i32;
+		view.features = flat_shape.features as i32;
+	}
+	view
+}
+
+fn scripted_opponent(name: &str, default: ScriptedOpponent) -> Result<ScriptedOpponent, SurgeError> {
+	match name {
+		"" => Ok(default),
+		name => ScriptedOpponent::from_name(name)
+			.ok_or_else(|| SurgeError::InvalidConfig(format!("unknown scripted opponent {}", name))),
+	}
+}
+
+fn mcts_config(settings: Option<&MctsSettings>) -> Result<MctsConfig, SurgeError> {
+	let mut mcts_config = MctsConfig::default();
+	let settings = match settings {
+		Some(settings) => settings,
+		None => return Ok(mcts_config),
+	};
+
+	if settings.playouts != 0 {
+		mcts_config.playouts = settings.playouts;
+	}
+	if settings.exploration != 0.0 {
+		mcts_config.exploration = settings.exploration;
+	}
+	mcts_config.rollout_policy = scripted_opponent(&settings.rollout_policy, mcts_config.rollout_policy)?;
+	if settings.rollout_depth != 0 {
+		mcts_config.rollout_depth = settings.rollout_depth;
+	}
+	if settings.use_prior_model {
+		mcts_config.prior_model = Some(settings.prior_model_id);
+	}
+	Ok(mcts_config)
+}
+
+fn matchmaking_kind(settings: Option<&MatchmakingSettings>) -> Result<MatchmakingKind, SurgeError> {
+	let settings = match settings {
+		Some(settings) if !settings.strategy.is_empty() => settings,
+		_ => return Ok(MatchmakingKind::Uniform),
+	};
+	let kind = MatchmakingKind::from_name(&settings.strategy)
+		.ok_or_else(|| SurgeError::InvalidConfig(format!("unknown matchmaking strategy {}", settings.strategy)))?;
+	Ok(match kind {
+		MatchmakingKind::Pfsp { .. } if settings.pfsp_exponent != 0.0 => MatchmakingKind::Pfsp { exponent: settings.pfsp_exponent },
+		MatchmakingKind::EloBand { .. } if settings.elo_band != 0.0 => MatchmakingKind::EloBand { width: settings.elo_band },
+		kind => kind,
+	})
+}
+
+fn engine_config(settings: Option<&EngineSettings>) -> Result<EngineConfig, SurgeError> {
+	let mut engine_config = EngineConfig::default();
+	engine_config.random_seed = thread_rng().gen::<u64>();
+	let settings = match settings {
+		Some(settings) => settings,
+		None => return Ok(engine_config),
+	};
+
+	if settings.board_size != 0 {
+		engine_config.board_size = settings.board_size.try_into()
+			.map_err(|_| SurgeError::InvalidConfig(format!("board_size {} is out of range", settings.board_size)))?;
+	}
+	if settings.fruit_density != 0.0 {
+		engine_config.fruit_density = settings.fruit_density;
+	}
+	if let Some(random_seed) = settings.random_seed {
+		engine_config.random_seed = random_seed;
+	}
+	if settings.fruit_types != 0 {
+		engine_config.fruit_types = settings.fruit_types.try_into()
+			.map_err(|_| SurgeError::InvalidConfig(format!("fruit_types {} is out of range", settings.fruit_types)))?;
+	}
+	if let Some(rewards) = &settings.rewards {
+		engine_config.win_reward = rewards.win_reward.unwrap_or(engine_config.win_reward);
+		engine_config.step_reward = rewards.step_reward.unwrap_or(engine_config.step_reward);
+	}
+	engine_config.validate().map_err(SurgeError::InvalidConfig)?;
+	Ok(engine_config)
+}
+
+fn surge_status(err: SurgeError) -> Status {
+	match err {
+		SurgeError::ModelNotFound(_) => Status::not_found(err.to_string()),
+		SurgeError::ModelLoad(_, _) => Status::failed_precondition(err.to_string()),
+		SurgeError::InvalidConfig(_) => Status::invalid_argument(err.to_string()),
+		SurgeError::Inference(_, _) | SurgeError::EngineInvariant(_) | SurgeError::Worker(_) => Status::internal(err.to_string()),
+		SurgeError::JobNotFound(_) => Status::not_found(err.to_string()),
+		SurgeError::QueueFull(_) => Status::resource_exhausted(err.to_string()),
+		SurgeError::LeagueAgentNotFound(_) => Status::not_found(err.to_string()),
+	}
+}
+
+fn io_status(err: io::Error) -> Status {
+	match err.kind() {
+		io::ErrorKind::InvalidData => Status::invalid_argument(err.to_string()),
+		_ => Status::internal(err.to_string()),
+	}
+}
+
+fn replay_view(replay: &MatchReplay, encoder: &dyn ObservationEncoder, advantage_config: Option<&AdvantageConfig>) -> MatchReplayView {
+	MatchReplayView {
+		sars: trajectory_view(replay, PlayerIdx::Player1, encoder, advantage_config),
+		opponent_sars: trajectory_view(replay, PlayerIdx::Player2, encoder, advantage_config),
+		player1_id: replay.agent_ids.0,
+		player2_id: replay.agent_ids.1,
+		result: replay.p1_won,
+		outcome: match_outcome(replay.outcome) as i32,
+		bootstrap_value: replay.bootstrap_values.0,
+		opponent_bootstrap_value: replay.bootstrap_values.1
+	}
+}
+
+// the match as seen by one seat, with advantages when the request asked for them
+fn trajectory_view(replay: &MatchReplay, idx: PlayerIdx, encoder: &dyn ObservationEncoder,
+				   advantage_config: Option<&AdvantageConfig>) -> Vec<SarView> {
+	let mut sars: Vec<SarView> = replay.sars.iter().map(|sar| sar_view(sar, idx, encoder)).collect();
+	if let Some(advantage_config) = advantage_config {
+		let (advantages, returns) = replay_advantages(replay, idx, advantage_config);
+		for ((sar, advantage), discounted_return) in sars.iter_mut().zip(advantages).zip(returns) {
+			sar.advantage = advantage;
+			sar.discounted_return = discounted_return;
+		}
+	}
+	sars
+}
+
+// encodes one step of a match from the point of view of the given seat
+fn sar_view(sar: &SAR, idx: PlayerIdx, encoder: &dyn ObservationEncoder) -> SarView {
+	let state = encoder.encode(&sar.gamestate, idx);
+	let (reward, action, action_mask, value, log_prob, logits) = match idx {
+		PlayerIdx::Player1 => (sar.rewards.0, sar.actions.0, &sar.action_masks.0, sar.values.0, sar.log_probs.0, &sar.logits.0),
+		PlayerIdx::Player2 => (sar.rewards.1, sar.actions.1, &sar.action_masks.1, sar.values.1, sar.log_probs.1, &sar.logits.1),
+	};
+
+	SarView {
+		state,
+		reward,
+		action: Action::as_num(action),
+		action_mask: action_mask.iter().map(|a| Action::as_num(*a)).collect(),
+		terminal: sar.terminal,
+		value,
+		truncated: sar.truncated,
+		log_prob,
+		advantage: 0.0f32,
+		discounted_return: 0.0f32,
+		logits: logits.clone()
+	}
+}
+
+fn match_outcome(outcome: WinState) -> MatchOutcomeView {
+	match outcome {
+		WinState::Finished(PlayerWinner::Player1) => MatchOutcomeView::Player1Won,
+		WinState::Finished(PlayerWinner::Player2) => MatchOutcomeView::Player2Won,
+		WinState::Draw => MatchOutcomeView::Draw,
+		// replays are only produced once a match is over, an unfinished one was cut off
+		WinState::Timeout | WinState::InProgress => MatchOutcomeView::Timeout,
+	}
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	let addr = "127.0.0.1:50051".parse()?;
-	let runner = MyMatchRunner::default();
+	let model_dir = PathBuf::from(env::var("SURGE_MODEL_DIR").unwrap_or_else(|_| "model_store".to_string()));
+	std::fs::create_dir_all(&model_dir)?;
+	let max_models = env::var("SURGE_MODEL_CACHE_SIZE").ok()
+		.and_then(|size| size.parse::<usize>().ok())
+		.unwrap_or(64);
+	let max_bytes = env::var("SURGE_MODEL_CACHE_BYTES").ok()
+		.and_then(|size| size.parse::<u64>().ok())
+		.unwrap_or(0);
+	let job_runners = env::var("SURGE_JOB_RUNNERS").ok()
+		.and_then(|runners| runners.parse::<usize>().ok())
+		.unwrap_or(2);
+	let job_queue_size = env::var("SURGE_JOB_QUEUE_SIZE").ok()
+		.and_then(|size| size.parse::<usize>().ok())
+		.unwrap_or(16);
+	let ratings_path = env::var("SURGE_RATINGS_FILE").unwrap_or_else(|_| "ratings.json".to_string());
+	let rating_system = env::var("SURGE_RATING_SYSTEM").unwrap_or_else(|_| "elo".to_string());
+	let rating_system = RatingSystem::from_name(&rating_system)
+		.ok_or_else(|| format!("SURGE_RATING_SYSTEM must be elo or trueskill, got {}", rating_system))?;
+	let league_path = env::var("SURGE_LEAGUE_FILE").unwrap_or_else(|_| "league.json".to_string());
+	let mut league_config = LeagueConfig::default();
+	if let Some(win_rate) = env::var("SURGE_LEAGUE_WIN_RATE").ok().and_then(|rate| rate.parse::<f32>().ok()) {
+		league_config.snapshot_win_rate = win_rate;
+	}
+	if let Some(min_matches) = env::var("SURGE_LEAGUE_MIN_MATCHES").ok().and_then(|matches| matches.parse::<u32>().ok()) {
+		league_config.min_matches = min_matches;
+	}
+	if let Some(max_matches) = env::var("SURGE_LEAGUE_MAX_MATCHES").ok().and_then(|matches| matches.parse::<u32>().ok()) {
+		league_config.max_matches = max_matches;
+	}
+	league_config.validate()?;
+	println!("Serving models from {}, caching up to {} models", model_dir.display(), max_models);
+	println!("Keeping {} ratings in {}", rating_system.name(), ratings_path);
+	println!("Keeping the league in {}", league_path);
+	let runner = MyMatchRunner::new(Arc::new(ModelStore::new(model_dir, max_models, max_bytes)),
+									Arc::new(RatingStore::open(ratings_path, rating_system)?),
+									Arc::new(LeagueStore::open(league_path, league_config)?),
+									JobQueue::new(job_runners, job_queue_size)?);
 
 	Server::builder()
 		.add_service(MatchRunnerServer::new(runner))
@@ -108,4 +808,75 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 		.await?;
 
 	Ok(())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use std::sync::Arc;
+	use super::*;
+	use super::start_match::{Action as ActionView, RewardSettings};
+	use game_runner::inference::ALL_LOGITS;
+
+	fn safetensors_bytes(tensors: &[(&str, Vec<usize>, Vec<f32>)]) -> Vec<u8> {
+		let mut header = vec![];
+		let mut data = vec![];
+		for (name, shape, values) in tensors {
+			let begin = data.len();
+			for value in values {
+				data.extend_from_slice(&value.to_le_bytes());
+			}
+			header.push(format!("\"{}\":{{\"dtype\":\"F32\",\"shape\":{:?},\"data_offsets\":[{},{}]}}",
+								name, shape, begin, data.len()));
+		}
+		let header = format!("{{{}}}", header.join(","));
+		let mut bytes = (header.len() as u64).to_le_bytes().to_vec();
+		bytes.extend_from_slice(header.as_bytes());
+		bytes.extend_from_slice(&data);
+		bytes
+	}
+
+	#[test]
+	fn engine_settings_keep_explicit_zeros() {
+		let settings = EngineSettings {
+			random_seed: Some(0),
+			rewards: Some(RewardSettings { win_reward: None, step_reward: Some(0.0) }),
+			..EngineSettings::default()
+		};
+		let engine_config = engine_config(Some(&settings)).unwrap();
+		assert_eq!(engine_config.random_seed, 0);
+		assert_eq!(engine_config.step_reward, 0.0);
+		assert_eq!(engine_config.win_reward, EngineConfig::default().win_reward);
+	}
+
+	#[test]
+	fn sar_view_reports_do_nothing() {
+		let model_dir = std::env::temp_dir().join(format!("surge-rpc-do-nothing-{}", std::process::id()));
+		let model_store = Arc::new(ModelStore::new(&model_dir, 4, 0));
+		// a policy that always prefers the fifth logit, DoNothing
+		let bytes = safetensors_bytes(&[
+			("0.weight", vec![2, 22], vec![0.0; 44]),
+			("0.bias", vec![2], vec![0.0; 2]),
+			("2.weight", vec![5, 2], vec![0.0; 10]),
+			("2.bias", vec![5], vec![0.0, 0.0, 0.0, 0.0, 10.0]),
+		]);
+		let spec = OutputSpec { layout: OutputLayout::Concatenated, num_logits: ALL_LOGITS, value_head: false };
+		model_store.save_model(1, ModelFormat::SafeTensors, spec, &bytes).unwrap();
+		let config = RunnerConfig {
+			target_id: 1,
+			max_matches: 1,
+			max_rounds: 3,
+			evaluation_mode: true,
+			record_logits: true,
+			observation_kind: ObservationKindView::Symbolic as i32,
+			..RunnerConfig::default()
+		};
+		let conf = rollout_config(&config).unwrap();
+		let encoder = conf.observation.build();
+
+		let replays = RolloutManager::new(conf, model_store, 1).play_matches().unwrap();
+		let view = sar_view(&replays[0].sars[0], PlayerIdx::Player1, encoder.as_ref());
+		assert_eq!(view.action, ActionView::DoNothing as i32);
+		assert_eq!(view.logits.len(), ALL_LOGITS);
+		std::fs::remove_dir_all(model_dir).unwrap();
+	}
+}